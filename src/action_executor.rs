@@ -0,0 +1,369 @@
+use std::fmt::{Display, Formatter};
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::system::SystemParam;
+use bevy::log::{info, warn};
+use bevy::prelude::{Commands, Entity, Event, EventReader, EventWriter, Has, IntoSystemConfigs, Query, Res, Transform, Visibility};
+use bevy_rapier3d::prelude::{ColliderDisabled, RigidBodyDisabled};
+use crate::Action;
+use crate::character::CharacterTrait;
+use crate::communication::{ChatMessage, MessageRole};
+use crate::inventory::{Inventory, InventoryError};
+use crate::item_registry::{ItemLookupError, ItemRegistry};
+use crate::navigation::{Destination, Locations, NavGrid, NavPath, NavigationError};
+use crate::npc::follow::Following;
+use crate::npc::npc::Npc;
+use crate::npc::resupply::{Resupplying, Stock};
+use crate::npc::shop::Shop;
+use crate::player::player::Player;
+use crate::quest::{self, QuestError, QuestLog, QuestRegistry};
+use crate::trade::{self, TradeDesk, TradeError, MAX_AMOUNT, MAX_GIFT_VALUE};
+
+pub struct ActionExecutorPlugin;
+
+impl Plugin for ActionExecutorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExecuteActionsEvent>();
+        app.add_event::<ActionOutcomeEvent>();
+        app.add_systems(Update, (execute_actions, record_action_outcomes).chain());
+    }
+}
+
+// Emitted when a character (player or npc) wants the actions of an Interaction carried out
+#[derive(Event)]
+pub struct ExecuteActionsEvent {
+    pub sender_id: String,
+    pub receiver_id: String,
+    pub actions: Vec<Action>,
+}
+
+// Emitted once for every executed (or rejected) action so the UI and the next prompt can reflect it
+#[derive(Event, Clone)]
+pub struct ActionOutcomeEvent {
+    pub sender_id: String,
+    pub receiver_id: String,
+    pub action: Action,
+    pub result: Result<(), ActionError>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ActionError {
+    UnknownCharacter(String),
+    UnknownItem(ItemLookupError),
+    // E.g. giving an item to yourself
+    InvalidTarget(String),
+    Inventory(InventoryError),
+    Navigation(NavigationError),
+    Quest(QuestError),
+    // The npc's shop is closed, so it doesn't trade
+    ShopClosed(String),
+    // The npc is on a resupply trip
+    Away(String),
+    Trade(TradeError),
+}
+
+impl Display for ActionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActionError::UnknownCharacter(name) => write!(f, "there is no one called {}", name),
+            ActionError::UnknownItem(err) => write!(f, "{}", err),
+            ActionError::InvalidTarget(reason) => write!(f, "{}", reason),
+            ActionError::Inventory(err) => write!(f, "{}", err),
+            ActionError::Navigation(err) => write!(f, "{}", err),
+            ActionError::Quest(err) => write!(f, "{}", err),
+            ActionError::ShopClosed(name) => write!(f, "the shop of {} is closed", name),
+            ActionError::Away(name) => write!(f, "{} is away resupplying", name),
+            ActionError::Trade(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Give { item, amount } => write!(f, "give {} {}", amount, item),
+            Action::Follow { target } => write!(f, "follow {}", target),
+            Action::StopFollowing => write!(f, "stop following"),
+            Action::Move { destination } => write!(f, "move to {}", destination),
+            Action::GiveQuest { quest } => write!(f, "give the quest {}", quest),
+            Action::CompleteQuest { quest } => write!(f, "complete the quest {}", quest),
+            Action::Open => write!(f, "open the shop"),
+            Action::Close => write!(f, "close the shop"),
+            Action::Resupply => write!(f, "go resupply"),
+            Action::Offer { give, want } => write!(f, "offer {} for {}", trade::describe_items(give), trade::describe_items(want)),
+            Action::Accept => write!(f, "accept the trade"),
+            Action::Reject => write!(f, "reject the trade"),
+        }
+    }
+}
+
+impl ActionOutcomeEvent {
+    // Human readable line describing what happened, used in logs and fed back to the model
+    pub fn describe(&self) -> String {
+        let action = match &self.action {
+            Action::Give { .. } | Action::GiveQuest { .. } | Action::Offer { .. } => format!("{} to {}", self.action, self.receiver_id),
+            Action::Accept | Action::Reject => format!("{} with {}", self.action, self.receiver_id),
+            Action::CompleteQuest { .. } => format!("{} of {}", self.action, self.receiver_id),
+            _ => self.action.to_string(),
+        };
+        match &self.result {
+            Ok(()) => format!("{} managed to {}", self.sender_id, action),
+            Err(err) => format!("{} tried to {} but it failed: {}", self.sender_id, action, err),
+        }
+    }
+}
+
+// Everything carrying out actions touches, the actions themselves are its methods
+#[derive(SystemParam)]
+struct Executor<'w, 's> {
+    commands: Commands<'w, 's>,
+    player_query: Query<'w, 's, (Entity, &'static Player)>,
+    npc_query: Query<'w, 's, (Entity, &'static Npc)>,
+    inventory_query: Query<'w, 's, &'static mut Inventory>,
+    transform_query: Query<'w, 's, &'static Transform>,
+    quest_log_query: Query<'w, 's, &'static mut QuestLog>,
+    shop_query: Query<'w, 's, &'static mut Shop>,
+    stock_query: Query<'w, 's, (&'static Stock, Has<Resupplying>)>,
+    item_registry: Res<'w, ItemRegistry>,
+    quest_registry: Res<'w, QuestRegistry>,
+    locations: Res<'w, Locations>,
+    nav_grid: Option<Res<'w, NavGrid>>,
+    trade_desk: TradeDesk<'w, 's>,
+}
+
+fn execute_actions(
+    mut executor: Executor,
+    mut on_execute_actions: EventReader<ExecuteActionsEvent>,
+    mut on_action_outcome: EventWriter<ActionOutcomeEvent>,
+) {
+    for req in on_execute_actions.read() {
+        let (sender_id, receiver_id) = (req.sender_id.as_str(), req.receiver_id.as_str());
+        for action in &req.actions {
+            let result = match action {
+                Action::Give { item, amount } => executor.give_item(sender_id, receiver_id, item, *amount),
+                Action::Follow { target } => executor.follow(sender_id, target),
+                Action::StopFollowing => executor.stop_following(sender_id),
+                Action::Move { destination } => executor.move_to(sender_id, destination),
+                Action::GiveQuest { quest } => executor.give_quest(sender_id, receiver_id, quest),
+                Action::CompleteQuest { quest } => executor.complete_quest(sender_id, receiver_id, quest),
+                Action::Open => executor.set_shop_open(sender_id, true),
+                Action::Close => executor.set_shop_open(sender_id, false),
+                Action::Resupply => executor.resupply(sender_id),
+                Action::Offer { .. } | Action::Accept | Action::Reject => executor.trade(sender_id, receiver_id, action),
+            };
+            on_action_outcome.send(ActionOutcomeEvent {
+                sender_id: req.sender_id.clone(),
+                receiver_id: req.receiver_id.clone(),
+                action: action.clone(),
+                result,
+            });
+        }
+    }
+}
+
+// Lets the npcs involved know what happened so the next prompt reflects it
+fn record_action_outcomes(
+    mut npc_query: Query<&mut Npc>,
+    mut on_action_outcome: EventReader<ActionOutcomeEvent>,
+) {
+    for outcome in on_action_outcome.read() {
+        let description = outcome.describe();
+        match outcome.result {
+            Ok(()) => info!("{}", description),
+            Err(_) => warn!("{}", description),
+        }
+        for mut npc in npc_query.iter_mut() {
+            if npc.name == outcome.sender_id || npc.name == outcome.receiver_id {
+                npc.message_history.push(ChatMessage::new(MessageRole::System, format!("Game: {}", description)));
+            }
+        }
+    }
+}
+
+impl Executor<'_, '_> {
+    fn find(&self, name: &str) -> Result<Entity, ActionError> {
+        find_character(&self.player_query, &self.npc_query, name)
+    }
+
+    // Moves items from the sender to the receiver. Either both inventories change or neither does
+    fn give_item(&mut self, sender_id: &str, receiver_id: &str, item_name: &str, amount: i32) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        let receiver = self.find(receiver_id)?;
+        if sender == receiver {
+            return Err(ActionError::InvalidTarget(format!("{} can't give items to themselves", sender_id)));
+        }
+        self.check_open_for_business([(sender, sender_id), (receiver, receiver_id)])?;
+        let item = self.item_registry.resolve(item_name).map_err(ActionError::UnknownItem)?;
+        if amount > MAX_AMOUNT {
+            return Err(ActionError::Inventory(InventoryError::InvalidAmount(amount)));
+        }
+        // Npcs can't be talked into handing out their goods, anything worth more than a small gift needs an Offer
+        if self.npc_query.contains(sender) && item.value(amount) > MAX_GIFT_VALUE {
+            return Err(ActionError::Trade(TradeError::GiftTooValuable { value: item.value(amount) }));
+        }
+
+        let [mut from, mut to] = self
+            .inventory_query
+            .get_many_mut([sender, receiver])
+            .map_err(|_| ActionError::InvalidTarget(format!("{} or {} can't hold items", sender_id, receiver_id)))?;
+        Inventory::transfer(&mut from, &mut to, item, amount).map_err(ActionError::Inventory)
+    }
+
+    // Shopkeepers don't trade after hours or while they're away, whichever way the items go
+    fn check_open_for_business(&self, characters: [(Entity, &str); 2]) -> Result<(), ActionError> {
+        for (entity, name) in characters {
+            if self.shop_query.get(entity).is_ok_and(|shop| !shop.open) {
+                return Err(ActionError::ShopClosed(name.to_string()));
+            }
+            self.check_not_away(entity, name)?;
+        }
+        Ok(())
+    }
+
+    // Offer, Accept and Reject. The sender makes or answers an offer, the receiver is the other side of the trade
+    fn trade(&mut self, sender_id: &str, receiver_id: &str, action: &Action) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        let receiver = self.find(receiver_id)?;
+        if sender == receiver {
+            return Err(ActionError::InvalidTarget(format!("{} can't trade with themselves", sender_id)));
+        }
+        let result = match action {
+            Action::Offer { give, want } => {
+                self.check_open_for_business([(sender, sender_id), (receiver, receiver_id)])?;
+                self.trade_desk.offer(&mut self.inventory_query, &self.item_registry, (sender, sender_id), (receiver, receiver_id), give, want)
+            }
+            Action::Accept => {
+                self.check_open_for_business([(sender, sender_id), (receiver, receiver_id)])?;
+                self.trade_desk.accept(&mut self.inventory_query, (sender, sender_id), (receiver, receiver_id))
+            }
+            // Walking away from a deal is always possible
+            _ => self.trade_desk.reject(&mut self.inventory_query, sender, receiver, receiver_id),
+        };
+        result.map_err(ActionError::Trade)
+    }
+
+    // Npcs on a resupply trip finish it before walking anywhere else
+    fn check_not_away(&self, entity: Entity, name: &str) -> Result<(), ActionError> {
+        if self.stock_query.get(entity).is_ok_and(|(_, away)| away) {
+            return Err(ActionError::Away(name.to_string()));
+        }
+        Ok(())
+    }
+
+    fn follow(&mut self, sender_id: &str, target_id: &str) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        let target = self.find(target_id)?;
+        self.check_not_away(sender, sender_id)?;
+        if sender == target {
+            return Err(ActionError::InvalidTarget(format!("{} can't follow themselves", sender_id)));
+        }
+        // Following someone replaces walking somewhere
+        self.commands.entity(sender).insert(Following { target }).remove::<NavPath>();
+        Ok(())
+    }
+
+    fn stop_following(&mut self, sender_id: &str) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        self.commands.entity(sender).remove::<Following>();
+        Ok(())
+    }
+
+    // Plans a path for the sender, they walk it over the next frames. Replaces following someone
+    fn move_to(&mut self, sender_id: &str, destination: &Destination) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        self.check_not_away(sender, sender_id)?;
+        let target = self.locations.resolve(destination).map_err(ActionError::Navigation)?;
+        let unreachable = || ActionError::Navigation(NavigationError::Unreachable(destination.to_string()));
+        let (Some(nav_grid), Ok(transform)) = (self.nav_grid.as_deref(), self.transform_query.get(sender)) else {
+            return Err(unreachable());
+        };
+        let path = NavPath::plan(nav_grid, transform.translation, target).ok_or_else(unreachable)?;
+        self.commands.entity(sender).insert(path).remove::<Following>();
+        Ok(())
+    }
+
+    // Adds a quest from the template pool to the receiver's quest log, if the sender offers it and can pay the reward
+    fn give_quest(&mut self, sender_id: &str, receiver_id: &str, quest_name: &str) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        let receiver = self.find(receiver_id)?;
+        let template = self.quest_registry.resolve(quest_name).map_err(ActionError::Quest)?;
+        let mut log = self
+            .quest_log_query
+            .get_mut(receiver)
+            .map_err(|_| ActionError::InvalidTarget(format!("{} doesn't take quests", receiver_id)))?;
+        let giver_inventory = self
+            .inventory_query
+            .get(sender)
+            .map_err(|_| ActionError::InvalidTarget(format!("{} can't hold items to reward", sender_id)))?;
+        quest::check_reward(template, &self.item_registry, giver_inventory).map_err(ActionError::Quest)?;
+        log.take(template, sender_id).map_err(ActionError::Quest)
+    }
+
+    // Pays out a quest the sender gave to the receiver, once the receiver met its objective
+    fn complete_quest(&mut self, sender_id: &str, receiver_id: &str, quest_name: &str) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        let receiver = self.find(receiver_id)?;
+        let mut log = self
+            .quest_log_query
+            .get_mut(receiver)
+            .map_err(|_| ActionError::InvalidTarget(format!("{} doesn't take quests", receiver_id)))?;
+        let quest = log.active_mut(sender_id, quest_name).map_err(ActionError::Quest)?;
+        let [mut giver_inventory, mut player_inventory] = self
+            .inventory_query
+            .get_many_mut([sender, receiver])
+            .map_err(|_| ActionError::InvalidTarget(format!("{} or {} can't hold items", sender_id, receiver_id)))?;
+        quest::turn_in(quest, &self.item_registry, &mut player_inventory, &mut giver_inventory).map_err(ActionError::Quest)
+    }
+
+    fn set_shop_open(&mut self, sender_id: &str, open: bool) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        let mut shop = self
+            .shop_query
+            .get_mut(sender)
+            .map_err(|_| ActionError::InvalidTarget(format!("{} doesn't have a shop", sender_id)))?;
+        shop.open = open;
+        Ok(())
+    }
+
+    // Sends the sender off to its supply location. When there's no way to walk there it disappears for a while instead
+    fn resupply(&mut self, sender_id: &str) -> Result<(), ActionError> {
+        let sender = self.find(sender_id)?;
+        let (stock, away) = self
+            .stock_query
+            .get(sender)
+            .map_err(|_| ActionError::InvalidTarget(format!("{} doesn't sell anything to resupply", sender_id)))?;
+        if away {
+            return Err(ActionError::Away(sender_id.to_string()));
+        }
+        let home = self.transform_query.get(sender).map(|transform| transform.translation).unwrap_or_default();
+        let path = self
+            .locations
+            .get(&stock.location)
+            .zip(self.nav_grid.as_deref())
+            .and_then(|(place, grid)| NavPath::plan(grid, home, place));
+        let mut sender = self.commands.entity(sender);
+        sender.remove::<Following>();
+        match path {
+            Some(path) => {
+                sender.insert((path, Resupplying::walking(home)));
+            }
+            None => {
+                sender.remove::<NavPath>().insert((Resupplying::away(home), Visibility::Hidden, ColliderDisabled, RigidBodyDisabled));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Finds the player or npc with the given name
+pub fn find_character(
+    player_query: &Query<(Entity, &Player)>,
+    npc_query: &Query<(Entity, &Npc)>,
+    name: &str,
+) -> Result<Entity, ActionError> {
+    player_query
+        .iter()
+        .find(|(_, player)| player.get_name() == name)
+        .map(|(entity, _)| entity)
+        .or_else(|| npc_query.iter().find(|(_, npc)| npc.get_name() == name).map(|(entity, _)| entity))
+        .ok_or(ActionError::UnknownCharacter(name.to_string()))
+}
@@ -1,3 +1,4 @@
+use bevy::audio::{AudioPlugin, SpatialScale};
 use bevy::prelude::*;
 use bevy_mod_billboard::plugin::BillboardPlugin;
 use bevy_rapier3d::{
@@ -6,25 +7,51 @@ use bevy_rapier3d::{
 };
 
 use crate::{
-    npc::npc_plugin::NpcPlugin, player::{
+    action_executor::ActionExecutorPlugin, inventory::InventoryPlugin, item_registry::ItemRegistryPlugin, llm::LlmPlugin, navigation::NavigationPlugin, quest::QuestPlugin, clock::ClockPlugin, npc::shop::ShopPlugin, npc::resupply::ResupplyPlugin, trade::TradePlugin, guard::GuardPlugin, npc::memory::MemoryPlugin, npc::npc_plugin::NpcPlugin, npc::npc_conversation::NpcConversationPlugin, npc::relationship::RelationshipPlugin, npc::follow::FollowPlugin, player::{
         camera_plugin::CameraPlugin, movement_plugin::PlayerMovementPlugin,
         player_plugin::PlayerPlugin,
-        actions_plugin::ActionsPlugin,
-    }, scene::ScenePlugin
+        actions_plugin::ActionsPlugin, chat_input_plugin::ChatInputPlugin, speech_bubble_plugin::SpeechBubblePlugin, dialogue_log_plugin::DialogueLogPlugin,
+    }, save::SavePlugin, scene::ScenePlugin, tts::TtsPlugin
 };
 
 pub fn launch_app() {
     App::new()
-        .add_plugins(DefaultPlugins)
+        // Npc voices fade with distance, a character is one unit wide so the scale keeps
+        // voices audible across a room instead of only right next to the npc
+        .add_plugins(DefaultPlugins.set(AudioPlugin {
+            default_spatial_scale: SpatialScale::new(0.2),
+            ..default()
+        }))
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugins(RapierDebugRenderPlugin::default())
+        .add_plugins(ItemRegistryPlugin)
+        .add_plugins(InventoryPlugin)
+        .add_plugins(LlmPlugin)
+        .add_plugins(TtsPlugin)
+        .add_plugins(ClockPlugin)
         .add_plugins(ScenePlugin)
+        .add_plugins(NavigationPlugin)
+        // Needs the ItemRegistryPlugin and NavigationPlugin above
+        .add_plugins(QuestPlugin)
         .add_plugins(NpcPlugin)
+        .add_plugins(MemoryPlugin)
+        .add_plugins(NpcConversationPlugin)
+        .add_plugins(RelationshipPlugin)
+        .add_plugins(FollowPlugin)
+        .add_plugins(ShopPlugin)
+        .add_plugins(ResupplyPlugin)
+        .add_plugins(TradePlugin)
+        .add_plugins(GuardPlugin)
         .add_plugins(PlayerPlugin)
         .add_plugins(CameraPlugin)
         .add_plugins(PlayerMovementPlugin)
         .add_plugins(bevy_tokio_tasks::TokioTasksPlugin::default())
         .add_plugins(BillboardPlugin)
+        .add_plugins(ChatInputPlugin)
+        .add_plugins(SpeechBubblePlugin)
+        .add_plugins(DialogueLogPlugin)
         .add_plugins(ActionsPlugin)
+        .add_plugins(ActionExecutorPlugin)
+        .add_plugins(SavePlugin)
         .run();
 }
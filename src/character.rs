@@ -3,19 +3,18 @@ use bevy::{
     asset::Assets,
     color::Color,
     pbr::{PbrBundle, StandardMaterial},
-    prelude::{default, Commands, Cuboid, Entity, Mesh, ResMut, Transform},
+    prelude::{default, Commands, Cuboid, Entity, Mesh, Transform},
 };
 use bevy_rapier3d::prelude::{Collider, Damping, ExternalForce, LockedAxes, RigidBody};
-use std::collections::HashMap;
-use crate::item::Item;
+use crate::inventory::Inventory;
 
 #[derive(Component)]
 pub struct Character;
 
 pub fn spawn_character_entity(
     commands: &mut Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
     color: Color,
     position: (f32, f32, f32),
 ) -> Entity {
@@ -38,13 +37,12 @@ pub fn spawn_character_entity(
             angular_damping: 0.9,
         })
         .insert(ExternalForce::default())
+        .insert(Inventory::default())
         .id()
 }
 
+// Items are not part of a character, they live in the Inventory component next to it
 pub trait CharacterTrait {
-    fn set_items(&mut self, new_items: HashMap<Item, i32>);
-    fn add_item(&mut self, item: Item, amount: i32);
-    fn remove_item(&mut self, item: Item, amount: i32) -> bool;
-    fn get_items(&self) -> &HashMap<Item, i32>;
+    fn get_name(&self) -> &str;
     fn print_self(&self);
 }
\ No newline at end of file
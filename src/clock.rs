@@ -0,0 +1,174 @@
+use std::fmt::{Display, Formatter};
+use bevy::app::{App, Plugin, Startup, Update};
+use bevy::color::Color;
+use bevy::pbr::AmbientLight;
+use bevy::prelude::{default, Commands, Component, IntoSystemConfigs, PositionType, Query, Res, ResMut, Resource, Style, Text, TextBundle, TextStyle, Time, Val, With};
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: f64 = 24.0 * 60.0;
+// A new game starts on Monday morning
+const START_HOUR: f64 = 8.0;
+
+// Daylight fades in between these hours and out between the dusk ones
+const DAWN: (f32, f32) = (5.0, 7.0);
+const DUSK: (f32, f32) = (19.0, 21.0);
+// Ambient light brightness at noon and at midnight
+const DAY_BRIGHTNESS: f32 = 80.0;
+const NIGHT_BRIGHTNESS: f32 = 10.0;
+
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GameClock::default());
+        app.add_systems(Startup, spawn_clock_display);
+        app.add_systems(Update, (advance_clock, update_daylight, update_clock_display).chain());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    pub const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+}
+
+impl Display for Weekday {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+// In-game time, counted in minutes since the game started
+#[derive(Resource)]
+pub struct GameClock {
+    minutes: f64,
+    // In-game minutes per real second, at 1.0 a day takes 24 minutes
+    pub time_scale: f32,
+}
+
+impl Default for GameClock {
+    fn default() -> Self {
+        Self {
+            minutes: START_HOUR * 60.0,
+            time_scale: 1.0,
+        }
+    }
+}
+
+impl GameClock {
+    pub fn minutes(&self) -> f64 {
+        self.minutes
+    }
+
+    pub fn set_minutes(&mut self, minutes: f64) {
+        self.minutes = minutes.max(0.0);
+    }
+
+    // Days since the game started, the first day is 0
+    pub fn day(&self) -> u32 {
+        (self.minutes / MINUTES_PER_DAY) as u32
+    }
+
+    pub fn weekday(&self) -> Weekday {
+        Weekday::ALL[self.day() as usize % 7]
+    }
+
+    pub fn hour(&self) -> u32 {
+        (self.minutes % MINUTES_PER_DAY / 60.0) as u32
+    }
+
+    pub fn minute(&self) -> u32 {
+        (self.minutes % 60.0) as u32
+    }
+
+    // Hours since midnight, with fractions
+    pub fn time_of_day(&self) -> f32 {
+        (self.minutes % MINUTES_PER_DAY / 60.0) as f32
+    }
+
+    // 0 at night, 1 during the day and in between at dawn and dusk
+    pub fn daylight(&self) -> f32 {
+        let hour = self.time_of_day();
+        let ramp = |(from, to): (f32, f32)| ((hour - from) / (to - from)).clamp(0.0, 1.0);
+        ramp(DAWN) - ramp(DUSK)
+    }
+
+    pub fn is_daytime(&self) -> bool {
+        self.daylight() >= 0.5
+    }
+}
+
+impl Display for GameClock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:02}:{:02}", self.weekday(), self.hour(), self.minute())
+    }
+}
+
+// The time as the model sees it in the NpcContext
+#[derive(Serialize)]
+pub struct TimeContext {
+    pub day: u32,
+    pub weekday: Weekday,
+    pub time: String,
+    pub daytime: bool,
+}
+
+impl TimeContext {
+    pub fn new(clock: &GameClock) -> TimeContext {
+        TimeContext {
+            day: clock.day(),
+            weekday: clock.weekday(),
+            time: format!("{:02}:{:02}", clock.hour(), clock.minute()),
+            daytime: clock.is_daytime(),
+        }
+    }
+}
+
+fn advance_clock(time: Res<Time>, mut clock: ResMut<GameClock>) {
+    clock.minutes += time.delta_seconds_f64() * clock.time_scale as f64;
+}
+
+fn update_daylight(clock: Res<GameClock>, mut ambient_light: ResMut<AmbientLight>) {
+    ambient_light.brightness = NIGHT_BRIGHTNESS + (DAY_BRIGHTNESS - NIGHT_BRIGHTNESS) * clock.daylight();
+}
+
+#[derive(Component)]
+struct ClockDisplay;
+
+fn spawn_clock_display(mut commands: Commands) {
+    commands.spawn((
+        TextBundle::from_section(String::new(), TextStyle { font_size: 20.0, color: Color::WHITE, ..default() }).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        ClockDisplay,
+    ));
+}
+
+fn update_clock_display(clock: Res<GameClock>, mut display_query: Query<&mut Text, With<ClockDisplay>>) {
+    for mut text in display_query.iter_mut() {
+        let shown = clock.to_string();
+        if text.sections[0].value != shown {
+            text.sections[0].value = shown;
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::llm::{LlmError, TokenSender};
 
 pub trait Communicator {
-    async fn talk(&mut self, message: ChatMessage) -> String;
+    async fn talk(&mut self, message: ChatMessage) -> Result<String, LlmError>;
+    // Same as talk, but the reply is also streamed into tokens while it is generated
+    async fn talk_streaming(&mut self, message: ChatMessage, tokens: TokenSender) -> Result<String, LlmError>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,12 +42,16 @@ pub struct ChatResponse {
 }
 
 impl ChatResponse {
+    pub fn new(message: ChatMessage) -> ChatResponse {
+        ChatResponse { message }
+    }
+
     pub fn get_message(&self) -> ChatMessage {
         self.message.clone()
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ChatRequest {
     model: String,
     messages: Vec<ChatMessage>,
@@ -59,4 +66,127 @@ impl ChatRequest {
             stream: false,
         }
     }
-}
\ No newline at end of file
+
+    // Same request but addressed to another model, backends use this to apply their configured model
+    pub fn with_model(&self, model: &str) -> ChatRequest {
+        ChatRequest {
+            model: model.to_string(),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_stream(&self, stream: bool) -> ChatRequest {
+        ChatRequest {
+            stream,
+            ..self.clone()
+        }
+    }
+
+    pub fn get_messages(&self) -> &Vec<ChatMessage> {
+        &self.messages
+    }
+}
+
+// Pulls the value of the "message" field out of a (possibly still incomplete) streamed Interaction json.
+// Returns None until the opening quote of the value has arrived
+pub fn extract_partial_message(raw: &str) -> Option<String> {
+    let key = raw.find("\"message\"")?;
+    let mut chars = raw[key + "\"message\"".len()..].chars().skip_while(|c| c.is_whitespace());
+    if chars.next()? != ':' {
+        return None;
+    }
+    let mut chars = chars.skip_while(|c| c.is_whitespace());
+    if chars.next()? != '"' {
+        return None;
+    }
+
+    let mut message = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => match chars.next() {
+                Some('n') => message.push('\n'),
+                Some('t') => message.push('\t'),
+                Some('r') => message.push('\r'),
+                Some('b') => message.push('\u{8}'),
+                Some('f') => message.push('\u{c}'),
+                Some('u') => match read_unicode_escape(&mut chars) {
+                    Some(decoded) => message.push(decoded),
+                    // Escape is cut off, wait for more input
+                    None => break,
+                },
+                // \", \\ and \/ stand for themselves
+                Some(escaped) => message.push(escaped),
+                None => break,
+            },
+            _ => message.push(c),
+        }
+    }
+    Some(message)
+}
+
+// Reads the hex digits after \u. Characters outside the basic plane come as two escapes, e.g. \uD83D\uDE00.
+// None when the input ends before the escape does, a surrogate without its other half becomes U+FFFD
+fn read_unicode_escape(chars: &mut (impl Iterator<Item = char> + Clone)) -> Option<char> {
+    let high = read_hex(chars)?;
+    if !(0xD800..0xDC00).contains(&high) {
+        return Some(char::from_u32(high).unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    // A high surrogate, the low one has to follow right away. Whatever follows instead is left to be read as usual
+    let mut ahead = chars.clone();
+    let (backslash, u) = (ahead.next()?, ahead.next()?);
+    if backslash != '\\' || u != 'u' {
+        return Some(char::REPLACEMENT_CHARACTER);
+    }
+    let low = read_hex(&mut ahead)?;
+    if !(0xDC00..0xE000).contains(&low) {
+        return Some(char::REPLACEMENT_CHARACTER);
+    }
+    *chars = ahead;
+    char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+}
+
+fn read_hex(chars: &mut impl Iterator<Item = char>) -> Option<u32> {
+    let code: Vec<char> = chars.take(4).collect();
+    if code.len() < 4 {
+        return None;
+    }
+    Some(u32::from_str_radix(&code.iter().collect::<String>(), 16).unwrap_or(0xFFFD))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_the_message_value() {
+        assert_eq!(extract_partial_message(r#"{"mess"#), None);
+        assert_eq!(extract_partial_message(r#"{"message": "#), None);
+        assert_eq!(extract_partial_message(r#"{"message": 3"#), None);
+        assert_eq!(extract_partial_message(r#"{"message": ""#), Some(String::new()));
+    }
+
+    #[test]
+    fn partial_and_complete_messages() {
+        assert_eq!(extract_partial_message(r#"{"message": "Hello, trav"#), Some("Hello, trav".to_string()));
+        assert_eq!(extract_partial_message(r#"{"message" :"Hello", "actions": []}"#), Some("Hello".to_string()));
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        let raw = r#"{"message": "a\"b\\c\/d\ne\tf\rg\bh\fi"}"#;
+        assert_eq!(extract_partial_message(raw), Some("a\"b\\c/d\ne\tf\rg\u{8}h\u{c}i".to_string()));
+        assert_eq!(extract_partial_message(r#"{"message": "café"}"#), Some("café".to_string()));
+        assert_eq!(extract_partial_message(r#"{"message": "caf\u00e9 \uD83D\uDE00!"}"#), Some("café 😀!".to_string()));
+        // A lone surrogate can't be shown, what follows it still is
+        assert_eq!(extract_partial_message(r#"{"message": "\uD83Dx1\uD83D\u0041"}"#), Some("\u{FFFD}x1\u{FFFD}A".to_string()));
+    }
+
+    #[test]
+    fn stops_before_a_cut_off_escape() {
+        assert_eq!(extract_partial_message(r#"{"message": "one\"#), Some("one".to_string()));
+        assert_eq!(extract_partial_message(r#"{"message": "caf\u00"#), Some("caf".to_string()));
+        assert_eq!(extract_partial_message(r#"{"message": "hi \uD83D"#), Some("hi ".to_string()));
+        assert_eq!(extract_partial_message(r#"{"message": "hi \uD83D\uDE"#), Some("hi ".to_string()));
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+use jsonschema::JSONSchema;
+use schemars::schema_for;
+use serde_json::Value;
+use crate::Interaction;
+use crate::communication::{ChatMessage, Communicator, MessageRole};
+use crate::llm::{LlmError, TokenSender};
+
+// How many times the model gets its validation error back and is asked to try again
+const MAX_REPAIR_ATTEMPTS: usize = 2;
+
+#[derive(Debug)]
+pub enum InteractionError {
+    // The model could not be reached at all
+    Llm(LlmError),
+    // The response doesn't contain anything that looks like a json object
+    NoJsonObject,
+    InvalidJson(String),
+    // The json is an object but doesn't match the Interaction schema
+    SchemaViolation(Vec<String>),
+}
+
+impl Display for InteractionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InteractionError::Llm(err) => write!(f, "the model could not be reached: {}", err),
+            InteractionError::NoJsonObject => write!(f, "the response does not contain a json object"),
+            InteractionError::InvalidJson(err) => write!(f, "the response is not valid json: {}", err),
+            InteractionError::SchemaViolation(errors) => write!(f, "the json does not match the expected format: {}", errors.join("; ")),
+        }
+    }
+}
+
+impl std::error::Error for InteractionError {}
+
+// The typed failure handed to the game once all repair attempts are used up
+#[derive(Debug)]
+pub struct ParseFailure {
+    pub attempts: usize,
+    pub error: InteractionError,
+    // The last thing the model said, if it said anything
+    pub response: Option<String>,
+}
+
+impl Display for ParseFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no valid interaction after {} attempts, {}", self.attempts, self.error)
+    }
+}
+
+// Compiled once, derived from the Interaction and Action types so it never drifts from them
+fn interaction_schema() -> &'static JSONSchema {
+    static SCHEMA: OnceLock<JSONSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        let schema = serde_json::to_value(schema_for!(Interaction)).expect("Interaction schema is valid json");
+        JSONSchema::compile(&schema).expect("Interaction schema compiles")
+    })
+}
+
+// Cuts code fences and any prose around the object, keeping the first object whose braces balance.
+// Braces inside strings don't count. A cut off object is kept whole so the json error says what's missing
+pub fn extract_json_object(raw: &str) -> Option<&str> {
+    let start = raw.find('{')?;
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in raw[start..].char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&raw[start..=start + i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(raw[start..].trim_end())
+}
+
+pub fn parse_interaction(raw: &str) -> Result<Interaction, InteractionError> {
+    let json = extract_json_object(raw).ok_or(InteractionError::NoJsonObject)?;
+    let value = serde_json::from_str::<Value>(json).map_err(|err| InteractionError::InvalidJson(err.to_string()))?;
+    if let Err(errors) = interaction_schema().validate(&value) {
+        return Err(InteractionError::SchemaViolation(
+            errors.map(|err| format!("{} at '{}'", err, err.instance_path)).collect(),
+        ));
+    }
+    serde_json::from_value::<Interaction>(value).map_err(|err| InteractionError::InvalidJson(err.to_string()))
+}
+
+// Sends the message and parses the reply. Invalid replies are sent back to the model together with
+// the validation error so it can correct itself, up to MAX_REPAIR_ATTEMPTS times
+pub async fn request_interaction<C: Communicator>(
+    communicator: &mut C,
+    message: ChatMessage,
+    tokens: TokenSender,
+) -> Result<Interaction, ParseFailure> {
+    let mut response = communicator.talk_streaming(message, tokens).await;
+    let mut attempts = 1;
+    loop {
+        let raw = match response {
+            Ok(raw) => raw,
+            Err(err) => return Err(ParseFailure { attempts, error: InteractionError::Llm(err), response: None }),
+        };
+        let error = match parse_interaction(&raw) {
+            Ok(interaction) => return Ok(interaction),
+            Err(error) => error,
+        };
+        if attempts > MAX_REPAIR_ATTEMPTS {
+            return Err(ParseFailure { attempts, error, response: Some(raw) });
+        }
+
+        let repair = ChatMessage::new(
+            MessageRole::User,
+            format!("Game: your last response could not be used because {}. Respond again with only the json object.", error),
+        );
+        response = communicator.talk(repair).await;
+        attempts += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use bevy::tasks::block_on;
+    use tokio::sync::mpsc::unbounded_channel;
+    use super::*;
+
+    const VALID: &str = r#"{"sender_id": "Bob", "receiver_id": "Hero", "message": "Hi {there}", "actions": []}"#;
+
+    // Replies with the scripted responses in order and remembers what it was sent
+    struct Scripted {
+        responses: VecDeque<String>,
+        sent: Vec<String>,
+    }
+
+    impl Scripted {
+        fn new(responses: &[&str]) -> Scripted {
+            Scripted { responses: responses.iter().map(|response| response.to_string()).collect(), sent: Vec::new() }
+        }
+    }
+
+    impl Communicator for Scripted {
+        async fn talk(&mut self, message: ChatMessage) -> Result<String, LlmError> {
+            self.sent.push(message.get_content());
+            self.responses.pop_front().ok_or(LlmError::Exhausted)
+        }
+
+        async fn talk_streaming(&mut self, message: ChatMessage, _tokens: TokenSender) -> Result<String, LlmError> {
+            self.talk(message).await
+        }
+    }
+
+    fn request(communicator: &mut Scripted) -> Result<Interaction, ParseFailure> {
+        let (tokens, _) = unbounded_channel();
+        block_on(request_interaction(communicator, ChatMessage::new(MessageRole::User, "Hello".to_string()), tokens))
+    }
+
+    #[test]
+    fn strips_code_fences_and_prose() {
+        let fenced = format!("```json\n{}\n```", VALID);
+        assert_eq!(extract_json_object(&fenced), Some(VALID));
+        let prose = format!("Sure! Here you go: {} Let me know if {{anything}} else is needed.", VALID);
+        assert_eq!(extract_json_object(&prose), Some(VALID));
+        assert_eq!(parse_interaction(&prose).unwrap().message, "Hi {there}");
+    }
+
+    #[test]
+    fn keeps_the_first_balanced_object() {
+        assert_eq!(extract_json_object(r#"{"a": {"b": "}"}} {"c": 1}"#), Some(r#"{"a": {"b": "}"}}"#));
+        assert_eq!(extract_json_object(r#"{"a": "\"}"} and more"#), Some(r#"{"a": "\"}"}"#));
+        assert_eq!(extract_json_object(r#"{"a": [1, "#), Some(r#"{"a": [1,"#));
+        assert_eq!(extract_json_object("no json here }"), None);
+    }
+
+    #[test]
+    fn reports_what_is_wrong() {
+        assert!(matches!(parse_interaction("I'd rather not"), Err(InteractionError::NoJsonObject)));
+        assert!(matches!(parse_interaction(r#"{"message": "Hi""#), Err(InteractionError::InvalidJson(_))));
+        let Err(InteractionError::SchemaViolation(errors)) = parse_interaction(r#"{"sender_id": "Bob", "message": 3, "actions": []}"#) else {
+            panic!("expected a schema violation");
+        };
+        assert!(errors.iter().any(|error| error.contains("receiver_id")), "{:?}", errors);
+        assert!(errors.iter().any(|error| error.contains("/message")), "{:?}", errors);
+    }
+
+    #[test]
+    fn repairs_invalid_responses() {
+        let mut communicator = Scripted::new(&["not json", VALID]);
+        assert_eq!(request(&mut communicator).unwrap().sender_id, "Bob");
+        assert_eq!(communicator.sent.len(), 2);
+        assert!(communicator.sent[1].contains("does not contain a json object"), "{}", communicator.sent[1]);
+    }
+
+    #[test]
+    fn gives_up_after_the_repair_attempts() {
+        let mut communicator = Scripted::new(&["no", "still no", "nope", VALID]);
+        let failure = request(&mut communicator).err().unwrap();
+        assert_eq!(failure.attempts, MAX_REPAIR_ATTEMPTS + 1);
+        assert_eq!(failure.response.as_deref(), Some("nope"));
+        assert_eq!(communicator.sent.len(), MAX_REPAIR_ATTEMPTS + 1);
+        assert!(matches!(failure.error, InteractionError::NoJsonObject));
+    }
+}
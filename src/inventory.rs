@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use bevy::app::{App, Plugin, PostUpdate};
+use bevy::log::debug;
+use bevy::prelude::{Changed, Component, Entity, Event, EventReader, EventWriter, IntoSystemConfigs, Query};
+use serde::{Deserialize, Serialize};
+use crate::item::Item;
+
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<InventoryChangedEvent>();
+        app.add_systems(PostUpdate, (emit_inventory_changes, log_inventory_changes).chain());
+    }
+}
+
+// Emitted for every item whose amount changed in an inventory, amount is negative when items were removed
+#[derive(Event, Clone, Debug)]
+pub struct InventoryChangedEvent {
+    pub entity: Entity,
+    pub item: Item,
+    pub amount: i32,
+}
+
+#[derive(Debug, Clone)]
+pub enum InventoryError {
+    InvalidAmount(i32),
+    NotEnough {
+        item: String,
+        has: i32,
+        wanted: i32,
+    },
+    // Adding would put more than capacity items in the inventory
+    OverCapacity {
+        capacity: i32,
+    },
+    OverWeight {
+        max_weight: u32,
+    },
+    // The amount or weight would no longer fit in a number
+    Overflow,
+}
+
+impl Display for InventoryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InventoryError::InvalidAmount(amount) => write!(f, "{} is not a valid amount", amount),
+            InventoryError::NotEnough { item, has, wanted } => write!(f, "only {} {} left but {} were needed", has, item, wanted),
+            InventoryError::OverCapacity { capacity } => write!(f, "there is only room for {} items", capacity),
+            InventoryError::OverWeight { max_weight } => write!(f, "that would weigh more than {}", max_weight),
+            InventoryError::Overflow => write!(f, "that is more than an inventory can hold"),
+        }
+    }
+}
+
+impl std::error::Error for InventoryError {}
+
+// Items held by a character. Amounts are checked, an operation either fully succeeds or changes nothing
+#[derive(Component, Clone, Default, Debug, Serialize, Deserialize)]
+pub struct Inventory {
+    #[serde(with = "serde_json_any_key::any_key_map")]
+    items: HashMap<Item, i32>,
+    // Maximum number of items, no limit when None
+    pub(crate) capacity: Option<i32>,
+    // Maximum combined weight of all items, no limit when None
+    pub(crate) max_weight: Option<u32>,
+    // Changes since the last InventoryChangedEvents were sent
+    #[serde(skip)]
+    changes: Vec<(Item, i32)>,
+}
+
+impl Inventory {
+    pub fn new(capacity: Option<i32>, max_weight: Option<u32>) -> Inventory {
+        Inventory {
+            capacity,
+            max_weight,
+            ..Default::default()
+        }
+    }
+
+    pub fn items(&self) -> &HashMap<Item, i32> {
+        &self.items
+    }
+
+    pub fn count(&self, item_id: &str) -> i32 {
+        self.items
+            .iter()
+            .find(|(item, _)| item.id == item_id)
+            .map(|(_, amount)| *amount)
+            .unwrap_or(0)
+    }
+
+    // Saturates, add keeps the real total below i32::MAX anyway
+    pub fn total_count(&self) -> i32 {
+        self.items.values().fold(0, |total, amount| total.saturating_add(*amount))
+    }
+
+    // Saturates, add keeps the real total below u32::MAX anyway
+    pub fn total_weight(&self) -> u32 {
+        self.items
+            .iter()
+            .map(|(item, amount)| item.weight.saturating_mul(*amount as u32))
+            .fold(0, |total, weight| total.saturating_add(weight))
+    }
+
+    // Checks whether add would succeed without changing anything
+    pub fn can_add(&self, item: &Item, amount: i32) -> Result<(), InventoryError> {
+        if amount <= 0 {
+            return Err(InventoryError::InvalidAmount(amount));
+        }
+        self.count(&item.id).checked_add(amount).ok_or(InventoryError::Overflow)?;
+        let total_count = self.total_count().checked_add(amount).ok_or(InventoryError::Overflow)?;
+        let total_weight = item.weight
+            .checked_mul(amount as u32)
+            .and_then(|weight| weight.checked_add(self.total_weight()))
+            .ok_or(InventoryError::Overflow)?;
+        if let Some(capacity) = self.capacity {
+            if total_count > capacity {
+                return Err(InventoryError::OverCapacity { capacity });
+            }
+        }
+        if let Some(max_weight) = self.max_weight {
+            if total_weight > max_weight {
+                return Err(InventoryError::OverWeight { max_weight });
+            }
+        }
+        Ok(())
+    }
+
+    // Checks whether remove would succeed without changing anything
+    pub fn can_remove(&self, item: &Item, amount: i32) -> Result<(), InventoryError> {
+        if amount <= 0 {
+            return Err(InventoryError::InvalidAmount(amount));
+        }
+        let has = self.count(&item.id);
+        if has < amount {
+            return Err(InventoryError::NotEnough { item: item.name.clone(), has, wanted: amount });
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, item: &Item, amount: i32) -> Result<(), InventoryError> {
+        self.can_add(item, amount)?;
+        match self.items.iter_mut().find(|(key, _)| key.id == item.id) {
+            Some((_, value)) => *value += amount,
+            None => {
+                self.items.insert(item.clone(), amount);
+            }
+        }
+        self.changes.push((item.clone(), amount));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, item: &Item, amount: i32) -> Result<(), InventoryError> {
+        self.can_remove(item, amount)?;
+        self.items.retain(|key, value| {
+            if key.id == item.id {
+                *value -= amount;
+            }
+            *value > 0
+        });
+        self.changes.push((item.clone(), -amount));
+        Ok(())
+    }
+
+    // Moves items from one inventory to another. Both change or, when either side can't, neither does
+    pub fn transfer(from: &mut Inventory, to: &mut Inventory, item: &Item, amount: i32) -> Result<(), InventoryError> {
+        from.can_remove(item, amount)?;
+        to.can_add(item, amount)?;
+        from.remove(item, amount)?;
+        to.add(item, amount)
+    }
+}
+
+fn emit_inventory_changes(
+    mut inventory_query: Query<(Entity, &mut Inventory), Changed<Inventory>>,
+    mut on_inventory_changed: EventWriter<InventoryChangedEvent>,
+) {
+    for (entity, mut inventory) in inventory_query.iter_mut() {
+        if inventory.changes.is_empty() {
+            continue;
+        }
+        for (item, amount) in inventory.changes.drain(..) {
+            on_inventory_changed.send(InventoryChangedEvent { entity, item, amount });
+        }
+    }
+}
+
+fn log_inventory_changes(mut on_inventory_changed: EventReader<InventoryChangedEvent>) {
+    for change in on_inventory_changed.read() {
+        debug!("{:?}: {:+} {}", change.entity, change.amount, change.item.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::item_registry::ItemRegistry;
+
+    fn item(id: &str) -> Item {
+        ItemRegistry::from_ron(include_str!("../assets/items.ron")).unwrap().get(id).unwrap().clone()
+    }
+
+    fn inventory(capacity: Option<i32>, max_weight: Option<u32>, items: &[(&Item, i32)]) -> Inventory {
+        let mut inventory = Inventory::new(capacity, max_weight);
+        for (item, amount) in items {
+            inventory.add(item, *amount).unwrap();
+        }
+        inventory.changes.clear();
+        inventory
+    }
+
+    fn snapshot(inventory: &Inventory) -> (HashMap<Item, i32>, usize) {
+        (inventory.items.clone(), inventory.changes.len())
+    }
+
+    #[test]
+    fn huge_amounts_overflow_instead_of_wrapping() {
+        let mut sword = item("steel_sword");
+        sword.weight = u32::MAX / 2;
+        let coin = item("gold_coin");
+        let mut inventory = inventory(None, None, &[(&coin, i32::MAX - 1)]);
+        assert!(matches!(inventory.add(&coin, 2), Err(InventoryError::Overflow)));
+        assert!(matches!(inventory.add(&item("bread"), 2), Err(InventoryError::Overflow)));
+        assert!(matches!(Inventory::new(None, Some(10)).can_add(&sword, 3), Err(InventoryError::Overflow)));
+        assert_eq!(inventory.count("gold_coin"), i32::MAX - 1);
+    }
+
+    #[test]
+    fn failed_remove_changes_nothing() {
+        let bread = item("bread");
+        let mut inventory = inventory(None, None, &[(&bread, 2)]);
+        let before = snapshot(&inventory);
+        assert!(matches!(inventory.remove(&bread, 3), Err(InventoryError::NotEnough { has: 2, wanted: 3, .. })));
+        assert!(matches!(inventory.remove(&bread, -1), Err(InventoryError::InvalidAmount(-1))));
+        assert_eq!(snapshot(&inventory), before);
+    }
+
+    #[test]
+    fn failed_transfer_leaves_both_inventories_unchanged() {
+        let (bread, coin) = (item("bread"), item("gold_coin"));
+        let mut from = inventory(None, None, &[(&bread, 5), (&coin, 10)]);
+        let mut to = inventory(Some(3), None, &[(&coin, 1)]);
+        let before = (snapshot(&from), snapshot(&to));
+
+        // The receiver has no room
+        assert!(matches!(Inventory::transfer(&mut from, &mut to, &bread, 3), Err(InventoryError::OverCapacity { capacity: 3 })));
+        // The giver doesn't have enough
+        assert!(matches!(Inventory::transfer(&mut from, &mut to, &bread, 6), Err(InventoryError::NotEnough { .. })));
+        assert_eq!((snapshot(&from), snapshot(&to)), before);
+
+        Inventory::transfer(&mut from, &mut to, &bread, 2).unwrap();
+        assert_eq!((from.count("bread"), to.count("bread")), (3, 2));
+    }
+}
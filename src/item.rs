@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Debug)]
 pub enum ItemType {
     Currency,
     Food,
@@ -9,25 +9,35 @@ pub enum ItemType {
     Misc,
 }
 
-#[derive(Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
+// What an item costs, expressed in a currency item
+#[derive(Clone, Eq, Hash, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Price {
+    // Id of the currency item, always gold_coin. Checked when the registry is loaded
+    pub(crate) currency: String,
+    pub(crate) amount: i32,
+}
+
+#[derive(Clone, Eq, Hash, PartialEq, Serialize, Deserialize, Debug)]
 pub struct Item {
+    // E.g. steel_sword. Stable, used to reference the item from data files
+    pub(crate) id: String,
     // E.g. Sword
     pub(crate) name: String,
     // E.g. ItemType::Weapon
     pub(crate) item_type: ItemType,
     // E.g. A steel sword. Simple but trustworthy
     pub(crate) description: String,
-    // E.g. Gold Coin, 1
-    pub(crate) value: (String, i32),
+    // E.g. 50 gold_coin
+    pub(crate) price: Price,
+    // E.g. 3, counts towards an inventory's max_weight
+    #[serde(default)]
+    pub(crate) weight: u32,
 }
 
 impl Item {
-    pub(crate) fn new(name: String, item_type: ItemType, description: String, value: (String, i32)) -> Item {
-        Item {
-            name,
-            item_type,
-            description,
-            value
-        }
+    // What amount of this item is worth in gold coins. The registry rejects prices in anything else, so values can be compared.
+    // Saturates instead of overflowing, amounts come from players and the model
+    pub fn value(&self, amount: i32) -> i32 {
+        self.price.amount.saturating_mul(amount)
     }
-}
\ No newline at end of file
+}
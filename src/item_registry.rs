@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use bevy::app::{App, Plugin};
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::Resource;
+use crate::item::{Item, ItemType};
+
+// Relative to the folder the assets folder lives in
+const ITEMS_FILE: &str = "assets/items.ron";
+
+// How many edits a name may be off by and still be suggested, e.g. "Steel Swrod"
+const MAX_TYPO_DISTANCE: usize = 2;
+
+// Every price is in this currency, so item values can be compared and added up
+pub const PRICE_CURRENCY: &str = "gold_coin";
+
+pub struct ItemRegistryPlugin;
+
+impl Plugin for ItemRegistryPlugin {
+    fn build(&self, app: &mut App) {
+        let path = FileAssetReader::get_base_path().join(ITEMS_FILE);
+        let data = fs::read_to_string(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path.display(), err));
+        let registry = ItemRegistry::from_ron(&data).unwrap_or_else(|err| panic!("Invalid {}: {}", path.display(), err));
+        app.insert_resource(registry);
+    }
+}
+
+// Every item that exists in the game, keyed by id
+#[derive(Resource)]
+pub struct ItemRegistry {
+    items: HashMap<String, Item>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ItemLookupError {
+    Unknown {
+        query: String,
+        // Closest known item name, if anything came close
+        suggestion: Option<String>,
+    },
+}
+
+impl Display for ItemLookupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ItemLookupError::Unknown { query, suggestion: Some(suggestion) } => {
+                write!(f, "there is no item called '{}', did you mean '{}'?", query, suggestion)
+            }
+            ItemLookupError::Unknown { query, suggestion: None } => write!(f, "there is no item called '{}'", query),
+        }
+    }
+}
+
+impl std::error::Error for ItemLookupError {}
+
+impl ItemRegistry {
+    // Parses a list of items and checks that ids are unique and every price is paid in gold coins
+    pub fn from_ron(data: &str) -> Result<ItemRegistry, String> {
+        let list = ron::from_str::<Vec<Item>>(data).map_err(|err| err.to_string())?;
+        let mut items = HashMap::new();
+        for item in list {
+            if let Some(duplicate) = items.insert(item.id.clone(), item) {
+                return Err(format!("item id '{}' is used more than once", duplicate.id));
+            }
+        }
+        match items.get(PRICE_CURRENCY) {
+            Some(currency) if currency.item_type == ItemType::Currency => {}
+            Some(_) => return Err(format!("'{}' is not a currency", PRICE_CURRENCY)),
+            None => return Err(format!("currency item '{}' is missing", PRICE_CURRENCY)),
+        }
+        if let Some(item) = items.values().find(|item| item.price.currency != PRICE_CURRENCY) {
+            return Err(format!("price of '{}' is in '{}', prices must be in '{}'", item.id, item.price.currency, PRICE_CURRENCY));
+        }
+        Ok(ItemRegistry { items })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Item> {
+        self.items.get(id)
+    }
+
+    // Finds the item a player or model meant by its id, its name or its name in plural. Near misses
+    // are not accepted, the error suggests the closest name instead so the caller can ask again
+    pub fn resolve(&self, query: &str) -> Result<&Item, ItemLookupError> {
+        let wanted = normalize(query);
+        if let Some(item) = self.items.get(&wanted.replace(' ', "_")) {
+            return Ok(item);
+        }
+        let by_name = |name: &str| self.items.values().find(|item| normalize(&item.name) == name);
+        if let Some(item) = by_name(&wanted) {
+            return Ok(item);
+        }
+        // "Swords", "Boxes". Only exact singular names count, so "glass" never turns into "glas"
+        for suffix in ["es", "s"] {
+            if let Some(item) = wanted.strip_suffix(suffix).and_then(|singular| by_name(singular)) {
+                return Ok(item);
+            }
+        }
+
+        let closest = self.items
+            .values()
+            .map(|item| (item, edit_distance(&normalize(&item.name), &wanted)))
+            .min_by_key(|(_, distance)| *distance);
+        let suggestion = match closest {
+            Some((item, distance)) if distance <= MAX_TYPO_DISTANCE.max(wanted.chars().count() / 2) => Some(item.name.clone()),
+            _ => None,
+        };
+        Err(ItemLookupError::Unknown { query: query.to_string(), suggestion })
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> ItemRegistry {
+        let mut registry = ItemRegistry::from_ron(include_str!("../assets/items.ron")).unwrap();
+        for (id, name) in [("boots", "Boots"), ("glass", "Glass"), ("box", "Box")] {
+            let mut item = registry.get("bread").unwrap().clone();
+            item.id = id.to_string();
+            item.name = name.to_string();
+            registry.items.insert(id.to_string(), item);
+        }
+        registry
+    }
+
+    fn suggestion(query: &str) -> Option<String> {
+        match registry().resolve(query) {
+            Err(ItemLookupError::Unknown { suggestion, .. }) => suggestion,
+            Ok(item) => panic!("'{}' resolved to {}", query, item.id),
+        }
+    }
+
+    #[test]
+    fn resolves_ids_names_and_plurals() {
+        let registry = registry();
+        assert_eq!(registry.resolve("steel_sword").unwrap().id, "steel_sword");
+        assert_eq!(registry.resolve("  steel   SWORD ").unwrap().id, "steel_sword");
+        assert_eq!(registry.resolve("Steel Swords").unwrap().id, "steel_sword");
+        assert_eq!(registry.resolve("boxes").unwrap().id, "box");
+    }
+
+    #[test]
+    fn names_ending_in_s_are_not_stripped() {
+        let registry = registry();
+        assert_eq!(registry.resolve("glass").unwrap().id, "glass");
+        assert_eq!(registry.resolve("boots").unwrap().id, "boots");
+        assert_eq!(registry.resolve("glasses").unwrap().id, "glass");
+    }
+
+    #[test]
+    fn typos_are_suggested_not_accepted() {
+        assert_eq!(suggestion("Steel Swrod"), Some("Steel Sword".to_string()));
+        assert_eq!(suggestion("bred"), Some("Bread".to_string()));
+        assert_eq!(suggestion("dragon egg"), None);
+    }
+
+    #[test]
+    fn prices_must_be_in_gold_coins() {
+        let data = include_str!("../assets/items.ron").replacen("currency: \"gold_coin\", amount: 2", "currency: \"bread\", amount: 2", 1);
+        let err = ItemRegistry::from_ron(&data).err().unwrap();
+        assert!(err.contains("prices must be in 'gold_coin'"), "{}", err);
+    }
+
+    #[test]
+    fn edit_distance_counts_single_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("bread", "bread"), 0);
+        assert_eq!(edit_distance("bread", "bred"), 1);
+        assert_eq!(edit_distance("bread", "breads"), 1);
+        assert_eq!(edit_distance("sword", "swrod"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
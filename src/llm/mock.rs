@@ -0,0 +1,45 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::sync::Mutex;
+use crate::communication::{ChatMessage, ChatRequest, ChatResponse, MessageRole};
+use crate::llm::{LlmBackend, LlmError, LlmFuture};
+
+// In-process stand-in for a model. Replies with the scripted responses in order, no server needed.
+// When looping is set the script starts over once it runs out, otherwise LlmError::Exhausted is returned
+#[derive(Default)]
+pub struct MockBackend {
+    script: Mutex<VecDeque<String>>,
+    looping: bool,
+}
+
+impl MockBackend {
+    pub fn new(responses: Vec<String>, looping: bool) -> MockBackend {
+        MockBackend {
+            script: Mutex::new(VecDeque::from(responses)),
+            looping,
+        }
+    }
+
+    // One response per line, empty lines are skipped. The script loops so the game can keep running on it
+    pub fn from_file(path: &str) -> std::io::Result<MockBackend> {
+        let responses = fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.to_string())
+            .collect();
+        Ok(MockBackend::new(responses, true))
+    }
+}
+
+impl LlmBackend for MockBackend {
+    fn send_msg<'a>(&'a self, _request: &'a ChatRequest) -> LlmFuture<'a> {
+        Box::pin(async move {
+            let mut script = self.script.lock().unwrap();
+            let response = script.pop_front().ok_or(LlmError::Exhausted)?;
+            if self.looping {
+                script.push_back(response.clone());
+            }
+            Ok(ChatResponse::new(ChatMessage::new(MessageRole::Assistant, response)))
+        })
+    }
+}
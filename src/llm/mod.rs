@@ -0,0 +1,104 @@
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use bevy::app::{App, Plugin};
+use bevy::prelude::Resource;
+use tokio::sync::mpsc::UnboundedSender;
+use crate::communication::{ChatRequest, ChatResponse};
+use crate::llm::mock::MockBackend;
+use crate::llm::ollama::OllamaBackend;
+use crate::llm::openai::OpenAiBackend;
+
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+
+const DEFAULT_MODEL: &str = "game-llm";
+
+// Boxed future returned by the backends so they can be used as a trait object
+pub type LlmFuture<'a> = Pin<Box<dyn Future<Output = Result<ChatResponse, LlmError>> + Send + 'a>>;
+
+// Receives the pieces of a streamed response as they come in
+pub type TokenSender = UnboundedSender<String>;
+
+#[derive(Debug)]
+pub enum LlmError {
+    Http(reqwest::Error),
+    // The backend answered but not in a shape we understand
+    InvalidResponse(String),
+    // The mock backend has nothing left to say
+    Exhausted,
+}
+
+impl Display for LlmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LlmError::Http(err) => write!(f, "http error: {}", err),
+            LlmError::InvalidResponse(msg) => write!(f, "invalid response: {}", msg),
+            LlmError::Exhausted => write!(f, "no scripted responses left"),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(err: reqwest::Error) -> Self {
+        LlmError::Http(err)
+    }
+}
+
+// Anything that can turn a chat history into the next assistant message.
+// Npc's Communicator impl goes through this, so models can be swapped without touching the npc code
+pub trait LlmBackend: Send + Sync {
+    fn send_msg<'a>(&'a self, request: &'a ChatRequest) -> LlmFuture<'a>;
+
+    // Like send_msg but every piece of the reply is also sent to tokens while it is being generated.
+    // Backends that can't stream send the whole reply as a single piece
+    fn stream_msg<'a>(&'a self, request: &'a ChatRequest, tokens: TokenSender) -> LlmFuture<'a> {
+        Box::pin(async move {
+            let response = self.send_msg(request).await?;
+            let _ = tokens.send(response.get_message().get_content());
+            Ok(response)
+        })
+    }
+}
+
+// The backend every NPC talks through. Picked once at startup, see LlmPlugin
+#[derive(Resource, Clone)]
+pub struct Llm {
+    pub backend: Arc<dyn LlmBackend>,
+}
+
+// Selects the backend from the environment:
+// LLM_BACKEND   ollama (default) | openai | mock
+// LLM_API_URL   base url of the server, e.g. http://localhost:11434
+// LLM_MODEL     model name, defaults to game-llm (see llm/Modelfile)
+// LLM_API_KEY   bearer token for OpenAI-compatible servers
+// LLM_MOCK_SCRIPT  file with one scripted response per line for the mock backend
+pub struct LlmPlugin;
+
+impl Plugin for LlmPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Llm { backend: backend_from_env() });
+    }
+}
+
+fn backend_from_env() -> Arc<dyn LlmBackend> {
+    let model = env::var("LLM_MODEL").unwrap_or(DEFAULT_MODEL.to_string());
+    let url = env::var("LLM_API_URL").ok();
+    match env::var("LLM_BACKEND").unwrap_or_default().as_str() {
+        "openai" => Arc::new(OpenAiBackend::new(
+            url.unwrap_or(openai::DEFAULT_API_URL.to_string()),
+            model,
+            env::var("LLM_API_KEY").ok(),
+        )),
+        "mock" => Arc::new(match env::var("LLM_MOCK_SCRIPT") {
+            Ok(path) => MockBackend::from_file(&path).expect("Could not read LLM_MOCK_SCRIPT"),
+            Err(_) => MockBackend::default(),
+        }),
+        _ => Arc::new(OllamaBackend::new(url.unwrap_or(ollama::DEFAULT_API_URL.to_string()), model)),
+    }
+}
@@ -0,0 +1,91 @@
+use serde::Deserialize;
+use crate::communication::{ChatMessage, ChatRequest, ChatResponse, MessageRole};
+use crate::llm::{LlmBackend, LlmError, LlmFuture, TokenSender};
+
+pub const DEFAULT_API_URL: &str = "http://localhost:11434";
+
+// Talks to the Ollama chat api (POST /api/chat)
+pub struct OllamaBackend {
+    http_client: reqwest::Client,
+    url: String,
+    model: String,
+}
+
+// One line of the NDJSON stream returned when stream is true
+#[derive(Deserialize)]
+struct StreamChunk {
+    message: Option<ChatMessage>,
+    error: Option<String>,
+}
+
+impl OllamaBackend {
+    pub fn new(url: String, model: String) -> OllamaBackend {
+        OllamaBackend {
+            http_client: reqwest::Client::new(),
+            url,
+            model,
+        }
+    }
+}
+
+impl LlmBackend for OllamaBackend {
+    fn send_msg<'a>(&'a self, request: &'a ChatRequest) -> LlmFuture<'a> {
+        Box::pin(async move {
+            let uri = format!("{}/api/chat", self.url);
+            let res = self.http_client
+                .post(uri)
+                .json::<ChatRequest>(&request.with_model(&self.model))
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<ChatResponse>()
+                .await?;
+
+            Ok(res)
+        })
+    }
+
+    fn stream_msg<'a>(&'a self, request: &'a ChatRequest, tokens: TokenSender) -> LlmFuture<'a> {
+        Box::pin(async move {
+            let uri = format!("{}/api/chat", self.url);
+            let mut res = self.http_client
+                .post(uri)
+                .json::<ChatRequest>(&request.with_model(&self.model).with_stream(true))
+                .send()
+                .await?
+                .error_for_status()?;
+
+            let mut buffer: Vec<u8> = Vec::new();
+            let mut content = String::new();
+            while let Some(chunk) = res.chunk().await? {
+                buffer.extend_from_slice(&chunk);
+                // Chunks don't line up with lines, only handle the lines that are complete
+                while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=end).collect();
+                    handle_stream_line(&line, &mut content, &tokens)?;
+                }
+            }
+            handle_stream_line(&buffer, &mut content, &tokens)?;
+
+            Ok(ChatResponse::new(ChatMessage::new(MessageRole::Assistant, content)))
+        })
+    }
+}
+
+fn handle_stream_line(line: &[u8], content: &mut String, tokens: &TokenSender) -> Result<(), LlmError> {
+    if line.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(());
+    }
+    let chunk = serde_json::from_slice::<StreamChunk>(line)
+        .map_err(|err| LlmError::InvalidResponse(err.to_string()))?;
+    if let Some(error) = chunk.error {
+        return Err(LlmError::InvalidResponse(error));
+    }
+    if let Some(message) = chunk.message {
+        let token = message.get_content();
+        content.push_str(&token);
+        // The receiving side may have gone away, the full reply is still returned
+        let _ = tokens.send(token);
+    }
+    Ok(())
+}
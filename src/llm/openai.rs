@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use crate::communication::{ChatMessage, ChatRequest, ChatResponse};
+use crate::llm::{LlmBackend, LlmError, LlmFuture};
+
+pub const DEFAULT_API_URL: &str = "http://localhost:8080";
+
+// Talks to any server implementing the OpenAI chat completions api (POST /v1/chat/completions).
+// This covers OpenAI itself as well as llama.cpp, vLLM, LM Studio and similar local servers
+pub struct OpenAiBackend {
+    http_client: reqwest::Client,
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a Vec<ChatMessage>,
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct CompletionChoice {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    choices: Vec<CompletionChoice>,
+}
+
+impl OpenAiBackend {
+    pub fn new(url: String, model: String, api_key: Option<String>) -> OpenAiBackend {
+        OpenAiBackend {
+            http_client: reqwest::Client::new(),
+            url,
+            model,
+            api_key,
+        }
+    }
+}
+
+impl LlmBackend for OpenAiBackend {
+    fn send_msg<'a>(&'a self, request: &'a ChatRequest) -> LlmFuture<'a> {
+        Box::pin(async move {
+            let uri = format!("{}/v1/chat/completions", self.url);
+            let body = CompletionRequest {
+                model: &self.model,
+                messages: request.get_messages(),
+                stream: false,
+            };
+            let mut req = self.http_client.post(uri).json(&body);
+            if let Some(api_key) = &self.api_key {
+                req = req.bearer_auth(api_key);
+            }
+            let res = req
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<CompletionResponse>()
+                .await?;
+
+            res.choices
+                .into_iter()
+                .next()
+                .map(|choice| ChatResponse::new(choice.message))
+                .ok_or(LlmError::InvalidResponse("response contained no choices".to_string()))
+        })
+    }
+}
@@ -1,50 +1,104 @@
 use std::collections::HashMap;
 use communication::Communicator;
 use crate::item::{Item};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json_any_key::*;
 use crate::character::CharacterTrait;
+use crate::navigation::Destination;
+use crate::npc::relationship::{RelationshipContext, Sentiment};
+use crate::quest::QuestContext;
+use crate::clock::TimeContext;
+use crate::npc::shop::ShopContext;
+use crate::npc::resupply::StockContext;
+use crate::trade::TradeItem;
 
 mod llm;
 mod communication;
 mod player;
 mod character;
 mod item;
+mod item_registry;
+mod inventory;
+mod save;
+mod tts;
 mod npc;
 
 mod app;
 mod scene;
+mod action_executor;
+mod interaction_parser;
+mod navigation;
+mod quest;
+mod clock;
+mod trade;
+mod guard;
 
 // Describes an action a player or npc can perform. These are passed along inside the Interaction struct.
-// For now only a give action exists. Some ideas:
-// 1. NPCs can issue a 'Follow' action where they follow the player around until directed otherwise
-// 2. 'Move' action where they can move to a certain location (Coordinates/named places) with path-finding.
-// 3. 'Quest' action so that they can give out quests to players
-// 4. 'Resupply': move to a certain location to stock up on new items (based on demand??!) if they run out
-// 6. 'Open': open their shop/business on their preferred times
-// 5. 'Close': close their shop/business
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 enum Action {
     Give {
         item: String,
         amount: i32,
-    }
+    },
+    // Walk after the character named target until StopFollowing
+    Follow {
+        target: String,
+    },
+    StopFollowing,
+    // Walk to a named place or to coordinates on the ground, finding a way around obstacles
+    Move {
+        destination: Destination,
+    },
+    // Hand the receiver one of the quests from assets/quests.ron, by id or title
+    GiveQuest {
+        quest: String,
+    },
+    // Take what the quest asked for from the receiver and pay out the reward, once they did the objective
+    CompleteQuest {
+        quest: String,
+    },
+    // Open or close the sender's shop, outside the opening hours too. Closed shops don't trade
+    Open,
+    Close,
+    // Go to the supply location and restock, bringing more of what sold well lately
+    Resupply,
+    // Propose a swap to the receiver, what's given is held back until the receiver accepts or rejects.
+    // Give is only for small gifts, anything worth more is traded
+    Offer {
+        give: Vec<TradeItem>,
+        want: Vec<TradeItem>,
+    },
+    // Accept or reject the offer the receiver made to the sender
+    Accept,
+    Reject,
 }
 
 // What the Player sends to the model (+ the NpcContext below) and what the model returns to the player (only this)
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct Interaction {
     sender_id: String,
     receiver_id: String,
     message: String,
     actions: Vec<Action>,
+    // How the message being answered made the sender feel about the receiver
+    #[serde(default)]
+    sentiment: Sentiment,
 }
 
 // Provides the model (npc) with the state of the npc it is responding as
-// For now only the items they have. But maybe things like their location, relations to other NPCs/players and more
-#[derive(Serialize, Deserialize)]
+// The items they have and what they think of whoever they're talking to. But maybe things like their location and more
+#[derive(Serialize)]
 struct NpcContext {
+    #[serde(with = "any_key_map")]
     npc_inventory: HashMap<Item, i32>,
+    relationship: RelationshipContext,
+    quests: QuestContext,
+    time: TimeContext,
+    // None when the npc doesn't run a shop
+    shop: Option<ShopContext>,
+    // None when the npc doesn't sell anything
+    stock: Option<StockContext>,
 }
 
 
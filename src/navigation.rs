@@ -0,0 +1,466 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use bevy::app::{App, Plugin, Update};
+use bevy::asset::io::file::FileAssetReader;
+use bevy::log::info;
+use bevy::math::{Vec2, Vec3};
+use bevy::prelude::{resource_exists, not, Commands, Component, Entity, IntoSystemConfigs, Query, RemovedComponents, Res, Resource, Transform, With, Without};
+use bevy_rapier3d::prelude::{Collider, ExternalForce, QueryFilter, RapierContext, RigidBody};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::character::Character;
+use crate::npc::follow::Following;
+use crate::player::movement_plugin::WALK_FORCE;
+
+// Relative to the folder the assets folder lives in
+const LOCATIONS_FILE: &str = "assets/locations.ron";
+
+// Colliders that don't reach higher than this are ground, not obstacles
+const STEP_HEIGHT: f32 = 0.2;
+// Colliders that start higher than this can be walked under
+const CHARACTER_HEIGHT: f32 = 1.0;
+
+// Costs of a straight and a diagonal step between cells, roughly 1 and sqrt(2)
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+pub struct NavigationPlugin;
+
+impl Plugin for NavigationPlugin {
+    fn build(&self, app: &mut App) {
+        let path = FileAssetReader::get_base_path().join(LOCATIONS_FILE);
+        let data = fs::read_to_string(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path.display(), err));
+        let locations = Locations::from_ron(&data).unwrap_or_else(|err| panic!("Invalid {}: {}", path.display(), err));
+        app.insert_resource(locations);
+        app.insert_resource(NavigationConfig::default());
+        app.add_systems(Update, build_nav_grid.run_if(not(resource_exists::<NavGrid>)));
+        app.add_systems(Update, (follow_paths, stop_walking));
+    }
+}
+
+#[derive(Resource)]
+pub struct NavigationConfig {
+    // Width of a grid cell
+    pub cell_size: f32,
+    // How far characters keep their center from obstacles, a bit more than half their width
+    pub clearance: f32,
+    pub force: f32,
+    // A waypoint counts as reached this close to it
+    pub arrive_distance: f32,
+    // How far ahead walkers look for other characters in their way
+    pub look_ahead: f32,
+}
+
+impl Default for NavigationConfig {
+    fn default() -> Self {
+        Self {
+            cell_size: 0.5,
+            clearance: 0.6,
+            force: WALK_FORCE,
+            arrive_distance: 0.4,
+            look_ahead: 1.0,
+        }
+    }
+}
+
+// Where the Move action can take a character, a named place or a spot on the ground
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Destination {
+    Named(String),
+    Coordinates {
+        x: f32,
+        z: f32,
+    },
+}
+
+impl Display for Destination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Destination::Named(name) => write!(f, "{}", name),
+            Destination::Coordinates { x, z } => write!(f, "({:.1}, {:.1})", x, z),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum NavigationError {
+    UnknownLocation {
+        name: String,
+        known: Vec<String>,
+    },
+    // Outside the map or walled off
+    Unreachable(String),
+}
+
+impl Display for NavigationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NavigationError::UnknownLocation { name, known } => write!(f, "there is no place called '{}', known places are {}", name, known.join(", ")),
+            NavigationError::Unreachable(destination) => write!(f, "there is no way to get to {}", destination),
+        }
+    }
+}
+
+impl std::error::Error for NavigationError {}
+
+// Named places from the locations file, keyed by name
+#[derive(Resource)]
+pub struct Locations {
+    places: HashMap<String, Vec2>,
+}
+
+impl Locations {
+    pub fn from_ron(data: &str) -> Result<Locations, String> {
+        let places = ron::from_str::<HashMap<String, (f32, f32)>>(data).map_err(|err| err.to_string())?;
+        Ok(Locations { places: places.into_iter().map(|(name, (x, z))| (name, Vec2::new(x, z))).collect() })
+    }
+
+    // Names are matched ignoring case, spaces and underscores are the same, so "Town Square" finds town_square
+    pub fn get(&self, name: &str) -> Option<Vec2> {
+        let normalize = |name: &str| name.trim().to_lowercase().replace(' ', "_");
+        let wanted = normalize(name);
+        self.places.iter().find(|(name, _)| normalize(name) == wanted).map(|(_, position)| *position)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.places.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    pub fn resolve(&self, destination: &Destination) -> Result<Vec2, NavigationError> {
+        match destination {
+            Destination::Named(name) => self.get(name).ok_or_else(|| NavigationError::UnknownLocation { name: name.clone(), known: self.names() }),
+            Destination::Coordinates { x, z } => Ok(Vec2::new(*x, *z)),
+        }
+    }
+}
+
+// Which parts of the ground can be walked on, built once from the static colliders of the scene
+#[derive(Resource)]
+pub struct NavGrid {
+    // Corner of cell (0, 0) on the xz plane
+    origin: Vec2,
+    cell_size: f32,
+    width: usize,
+    depth: usize,
+    blocked: Vec<bool>,
+}
+
+type Cell = (usize, usize);
+
+impl NavGrid {
+    // ground is the xz area that can be walked on, obstacles the xz areas of everything standing on it
+    pub fn new(ground: (Vec2, Vec2), obstacles: &[(Vec2, Vec2)], cell_size: f32, clearance: f32) -> NavGrid {
+        let (min, max) = ground;
+        let width = ((max.x - min.x) / cell_size).floor().max(0.0) as usize;
+        let depth = ((max.y - min.y) / cell_size).floor().max(0.0) as usize;
+        let mut grid = NavGrid { origin: min, cell_size, width, depth, blocked: vec![false; width * depth] };
+        for x in 0..width {
+            for z in 0..depth {
+                let center = grid.cell_center((x, z));
+                // Characters can't stand half off the edge of the ground either
+                let near_edge = center.x - min.x < clearance || max.x - center.x < clearance || center.y - min.y < clearance || max.y - center.y < clearance;
+                let near_obstacle = obstacles.iter().any(|(low, high)| {
+                    center.x > low.x - clearance && center.x < high.x + clearance && center.y > low.y - clearance && center.y < high.y + clearance
+                });
+                grid.blocked[z * width + x] = near_edge || near_obstacle;
+            }
+        }
+        grid
+    }
+
+    fn cell_center(&self, (x, z): Cell) -> Vec2 {
+        self.origin + Vec2::new(x as f32 + 0.5, z as f32 + 0.5) * self.cell_size
+    }
+
+    fn cell_at(&self, position: Vec2) -> Option<Cell> {
+        let local = (position - self.origin) / self.cell_size;
+        if local.x < 0.0 || local.y < 0.0 || local.x >= self.width as f32 || local.y >= self.depth as f32 {
+            return None;
+        }
+        Some((local.x as usize, local.y as usize))
+    }
+
+    fn is_walkable(&self, (x, z): Cell) -> bool {
+        x < self.width && z < self.depth && !self.blocked[z * self.width + x]
+    }
+
+    // Closest walkable cell to a position, for characters pushed against a wall or destinations inside something
+    fn nearest_walkable(&self, position: Vec2) -> Option<Cell> {
+        let local = ((position - self.origin) / self.cell_size).floor();
+        let x = (local.x.max(0.0) as usize).min(self.width.saturating_sub(1));
+        let z = (local.y.max(0.0) as usize).min(self.depth.saturating_sub(1));
+        (0..self.width.max(self.depth)).find_map(|radius| {
+            let mut ring = Vec::new();
+            for dx in -(radius as isize)..=radius as isize {
+                for dz in -(radius as isize)..=radius as isize {
+                    if dx.unsigned_abs() != radius && dz.unsigned_abs() != radius {
+                        continue;
+                    }
+                    let cell = (x.wrapping_add_signed(dx), z.wrapping_add_signed(dz));
+                    if self.is_walkable(cell) {
+                        ring.push(cell);
+                    }
+                }
+            }
+            ring.into_iter().min_by(|a, b| self.cell_center(*a).distance(position).total_cmp(&self.cell_center(*b).distance(position)))
+        })
+    }
+
+    fn neighbours(&self, (x, z): Cell) -> Vec<(Cell, u32)> {
+        let mut neighbours = Vec::new();
+        for (dx, dz) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let cell = (x.wrapping_add_signed(dx), z.wrapping_add_signed(dz));
+            if !self.is_walkable(cell) {
+                continue;
+            }
+            if dx != 0 && dz != 0 {
+                // No cutting corners past an obstacle
+                if !self.is_walkable((x.wrapping_add_signed(dx), z)) || !self.is_walkable((x, z.wrapping_add_signed(dz))) {
+                    continue;
+                }
+                neighbours.push((cell, DIAGONAL_COST));
+            } else {
+                neighbours.push((cell, STRAIGHT_COST));
+            }
+        }
+        neighbours
+    }
+
+    // Octile distance, never more than the real cost so A* still finds the shortest path
+    fn heuristic((ax, az): Cell, (bx, bz): Cell) -> u32 {
+        let dx = ax.abs_diff(bx) as u32;
+        let dz = az.abs_diff(bz) as u32;
+        STRAIGHT_COST * dx.max(dz) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dz)
+    }
+
+    // A* from start to goal. Returns the points to walk through on the xz plane, ending at goal,
+    // or None when goal can't be reached. A goal inside an obstacle is moved to the closest spot next to it
+    pub fn find_path(&self, start: Vec2, goal: Vec2) -> Option<Vec<Vec2>> {
+        let start_cell = self.cell_at(start).filter(|cell| self.is_walkable(*cell)).or_else(|| self.nearest_walkable(start))?;
+        let goal_cell = match self.cell_at(goal) {
+            Some(cell) if self.is_walkable(cell) => cell,
+            Some(_) => self.nearest_walkable(goal)?,
+            None => return None,
+        };
+
+        let mut open = BinaryHeap::new();
+        let mut came_from = HashMap::<Cell, Cell>::new();
+        let mut cost = HashMap::<Cell, u32>::new();
+        cost.insert(start_cell, 0);
+        open.push(Reverse((Self::heuristic(start_cell, goal_cell), start_cell)));
+        while let Some(Reverse((estimate, cell))) = open.pop() {
+            let current = cost[&cell];
+            // A cheaper way to this cell was found after this entry was pushed, it's already been expanded
+            if estimate > current + Self::heuristic(cell, goal_cell) {
+                continue;
+            }
+            if cell == goal_cell {
+                let mut cells = vec![cell];
+                while let Some(previous) = came_from.get(cells.last().unwrap()) {
+                    cells.push(*previous);
+                }
+                cells.reverse();
+                let mut path = simplify(&cells).into_iter().skip(1).map(|cell| self.cell_center(cell)).collect::<Vec<_>>();
+                // End exactly on the destination when it's walkable, not just in the middle of its cell
+                if self.cell_at(goal) == Some(goal_cell) {
+                    path.pop();
+                    path.push(goal);
+                }
+                return Some(path);
+            }
+            for (next, step) in self.neighbours(cell) {
+                let next_cost = current + step;
+                if cost.get(&next).is_some_and(|known| *known <= next_cost) {
+                    continue;
+                }
+                cost.insert(next, next_cost);
+                came_from.insert(next, cell);
+                open.push(Reverse((next_cost + Self::heuristic(next, goal_cell), next)));
+            }
+        }
+        None
+    }
+}
+
+// Keeps only the cells where the path changes direction
+fn simplify(cells: &[Cell]) -> Vec<Cell> {
+    let direction = |a: Cell, b: Cell| (b.0 as isize - a.0 as isize, b.1 as isize - a.1 as isize);
+    let mut kept = Vec::new();
+    for (i, cell) in cells.iter().enumerate() {
+        let is_turn = i > 0 && i + 1 < cells.len() && direction(cells[i - 1], *cell) != direction(*cell, cells[i + 1]);
+        if i == 0 || i + 1 == cells.len() || is_turn {
+            kept.push(*cell);
+        }
+    }
+    kept
+}
+
+// The waypoints a character still has to walk through, added by the Move action
+#[derive(Component)]
+pub struct NavPath {
+    pub waypoints: VecDeque<Vec3>,
+}
+
+impl NavPath {
+    // Plans a path for a character standing at from. Waypoints keep the character's height, forces only push sideways
+    pub fn plan(grid: &NavGrid, from: Vec3, to: Vec2) -> Option<NavPath> {
+        let path = grid.find_path(Vec2::new(from.x, from.z), to)?;
+        Some(NavPath { waypoints: path.into_iter().map(|point| Vec3::new(point.x, from.y, point.y)).collect() })
+    }
+}
+
+// Ground is every static collider that's flat enough to stand on, obstacles are the rest.
+// Static means without a RigidBody, so scene colliders must not carry RigidBody::Fixed or they're left out of the grid.
+// Runs until the scene has spawned its colliders
+fn build_nav_grid(
+    mut commands: Commands,
+    config: Res<NavigationConfig>,
+    collider_query: Query<(&Collider, &Transform), Without<RigidBody>>,
+) {
+    let mut ground: Option<(Vec2, Vec2)> = None;
+    let mut obstacles = Vec::new();
+    for (collider, transform) in collider_query.iter() {
+        let aabb = collider.raw.compute_local_aabb();
+        let (mut low, mut high) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+        for corner in aabb.vertices() {
+            let corner = transform.transform_point(Vec3::new(corner.x, corner.y, corner.z));
+            low = low.min(corner);
+            high = high.max(corner);
+        }
+        let area = (Vec2::new(low.x, low.z), Vec2::new(high.x, high.z));
+        if high.y <= STEP_HEIGHT {
+            ground = Some(match ground {
+                Some((min, max)) => (min.min(area.0), max.max(area.1)),
+                None => area,
+            });
+        } else if low.y < CHARACTER_HEIGHT {
+            obstacles.push(area);
+        }
+    }
+    let Some(ground) = ground else {
+        return;
+    };
+    let grid = NavGrid::new(ground, &obstacles, config.cell_size, config.clearance);
+    info!("Built a {}x{} navigation grid around {} obstacles", grid.width, grid.depth, obstacles.len());
+    commands.insert_resource(grid);
+}
+
+// Pushes characters along their path, dropping waypoints as they're reached
+fn follow_paths(
+    mut commands: Commands,
+    config: Res<NavigationConfig>,
+    rapier_context: Res<RapierContext>,
+    mut walkers: Query<(Entity, &Transform, &mut NavPath, &mut ExternalForce), With<Character>>,
+) {
+    for (entity, transform, mut path, mut force) in walkers.iter_mut() {
+        while path.waypoints.front().is_some_and(|waypoint| horizontal(*waypoint - transform.translation).length() <= config.arrive_distance) {
+            path.waypoints.pop_front();
+        }
+        let Some(waypoint) = path.waypoints.front() else {
+            commands.entity(entity).remove::<NavPath>();
+            continue;
+        };
+        let direction = horizontal(*waypoint - transform.translation).normalize_or_zero();
+        let direction = avoid_obstacles(&rapier_context, entity, None, transform.translation, direction, config.look_ahead);
+        force.force = direction * config.force;
+    }
+}
+
+// Characters that arrived, stopped following or had their path taken away shouldn't keep drifting with their last force
+fn stop_walking(mut removed_paths: RemovedComponents<NavPath>, mut removed_follows: RemovedComponents<Following>, mut forces: Query<&mut ExternalForce>) {
+    for entity in removed_paths.read().chain(removed_follows.read()) {
+        if let Ok(mut force) = forces.get_mut(entity) {
+            force.force = Vec3::ZERO;
+        }
+    }
+}
+
+fn horizontal(vector: Vec3) -> Vec3 {
+    Vec3::new(vector.x, 0.0, vector.z)
+}
+
+// Bends a horizontal direction around the first collider in the way, so characters slide along obstacles
+// instead of pushing into them. ignore is left out of the check, e.g. the character being walked to
+pub fn avoid_obstacles(
+    rapier_context: &RapierContext,
+    entity: Entity,
+    ignore: Option<Entity>,
+    position: Vec3,
+    direction: Vec3,
+    look_ahead: f32,
+) -> Vec3 {
+    let mut filter = QueryFilter::new().exclude_collider(entity).exclude_sensors();
+    if let Some(ignore) = ignore {
+        filter = filter.exclude_collider(ignore);
+    }
+    let Some((_, hit)) = rapier_context.cast_ray_and_get_normal(position, direction, look_ahead, true, filter) else {
+        return direction;
+    };
+    let normal = Vec3::new(hit.normal.x, 0.0, hit.normal.z).normalize_or_zero();
+    let mut along = direction - normal * direction.dot(normal);
+    // Walking straight into it, pick a side
+    if along.length() < 0.1 {
+        along = normal.cross(Vec3::Y);
+    }
+    (along.normalize_or_zero() + normal * 0.5).normalize_or_zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 by 10 ground with a wall across most of it at x 4 to 6, only open at the far end
+    fn walled_grid() -> NavGrid {
+        NavGrid::new((Vec2::ZERO, Vec2::splat(10.0)), &[(Vec2::new(4.0, 0.0), Vec2::new(6.0, 8.0))], 1.0, 0.0)
+    }
+
+    #[test]
+    fn finds_a_path_around_an_obstacle() {
+        let grid = walled_grid();
+        let goal = Vec2::new(8.2, 1.7);
+        let path = grid.find_path(Vec2::new(1.5, 1.5), goal).expect("the goal is reachable around the wall");
+        assert_eq!(path.last(), Some(&goal));
+        assert!(path.iter().any(|point| point.y > 8.0), "the path has to go around the end of the wall: {:?}", path);
+        for point in &path {
+            assert!(grid.is_walkable(grid.cell_at(*point).unwrap()), "{:?} is inside the wall", point);
+        }
+    }
+
+    #[test]
+    fn no_path_to_a_walled_off_goal() {
+        let walls = [(Vec2::new(6.0, 6.0), Vec2::new(10.0, 7.0)), (Vec2::new(6.0, 6.0), Vec2::new(7.0, 10.0))];
+        let grid = NavGrid::new((Vec2::ZERO, Vec2::splat(10.0)), &walls, 1.0, 0.0);
+        assert_eq!(grid.find_path(Vec2::new(1.5, 1.5), Vec2::new(8.5, 8.5)), None);
+    }
+
+    #[test]
+    fn no_path_off_the_ground() {
+        assert_eq!(walled_grid().find_path(Vec2::new(1.5, 1.5), Vec2::new(12.0, 1.5)), None);
+    }
+
+    #[test]
+    fn goal_inside_an_obstacle_ends_next_to_it() {
+        let grid = walled_grid();
+        assert_eq!(grid.nearest_walkable(Vec2::new(4.2, 3.5)), Some((3, 3)));
+        let path = grid.find_path(Vec2::new(1.5, 3.5), Vec2::new(4.2, 3.5)).unwrap();
+        assert_eq!(path.last(), Some(&grid.cell_center((3, 3))));
+    }
+
+    #[test]
+    fn nearest_walkable_of_a_walkable_spot_is_its_own_cell() {
+        assert_eq!(walled_grid().nearest_walkable(Vec2::new(7.3, 2.9)), Some((7, 2)));
+    }
+
+    #[test]
+    fn simplify_keeps_only_the_turns() {
+        let cells = [(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (3, 3)];
+        assert_eq!(simplify(&cells), vec![(0, 0), (2, 0), (2, 2), (3, 3)]);
+        assert_eq!(simplify(&[(4, 4)]), vec![(4, 4)]);
+        assert_eq!(simplify(&[]), Vec::<Cell>::new());
+    }
+}
@@ -0,0 +1,64 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::math::Vec3;
+use bevy::prelude::{Commands, Component, Entity, Query, Res, Resource, Transform};
+use bevy_rapier3d::prelude::{ExternalForce, RapierContext};
+use crate::navigation::avoid_obstacles;
+use crate::player::movement_plugin::WALK_FORCE;
+
+pub struct FollowPlugin;
+
+impl Plugin for FollowPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(FollowConfig::default());
+        app.add_systems(Update, steer_followers);
+    }
+}
+
+#[derive(Resource)]
+pub struct FollowConfig {
+    // Followers stop once they are this close to their target
+    pub distance: f32,
+    pub force: f32,
+    // How far ahead followers look for something in their way
+    pub look_ahead: f32,
+}
+
+impl Default for FollowConfig {
+    fn default() -> Self {
+        Self {
+            distance: 1.5,
+            force: WALK_FORCE,
+            look_ahead: 1.5,
+        }
+    }
+}
+
+// Added to a character by the Follow action, removed by StopFollowing or when the target is gone.
+// The navigation's stop_walking zeroes the force once it's removed
+#[derive(Component)]
+pub struct Following {
+    pub target: Entity,
+}
+
+fn steer_followers(
+    mut commands: Commands,
+    config: Res<FollowConfig>,
+    rapier_context: Res<RapierContext>,
+    mut followers: Query<(Entity, &Transform, &Following, &mut ExternalForce)>,
+    targets: Query<&Transform>,
+) {
+    for (entity, transform, following, mut force) in followers.iter_mut() {
+        let Ok(target) = targets.get(following.target) else {
+            commands.entity(entity).remove::<Following>();
+            continue;
+        };
+        let mut offset = target.translation - transform.translation;
+        offset.y = 0.0;
+        if offset.length() <= config.distance {
+            force.force = Vec3::ZERO;
+            continue;
+        }
+        let direction = avoid_obstacles(&rapier_context, entity, Some(following.target), transform.translation, offset.normalize(), config.look_ahead);
+        force.force = direction * config.force;
+    }
+}
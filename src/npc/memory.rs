@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use bevy::app::{App, Plugin, Update};
+use bevy::log::{info, warn};
+use bevy::prelude::{Query, Res, ResMut, Resource};
+use bevy::tasks::{block_on, futures_lite::future};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use tokio::task::JoinHandle;
+use crate::communication::{ChatMessage, ChatRequest, MessageRole};
+use crate::llm::LlmError;
+use crate::npc::npc::Npc;
+
+// Rough average for english text, good enough to decide when to compact
+const CHARS_PER_TOKEN: usize = 4;
+
+const SUMMARY_PROMPT: &str = "You maintain the memory of a NPC in a RPG game. You will be given the earlier part of the \
+conversations this NPC had, possibly starting with an older memory. Summarize it into a short text written from the NPC's \
+point of view. Keep names, promises, deals, prices, item transfers and anything the NPC would remember. Only respond with the summary.";
+
+const MEMORY_PREFIX: &str = "Memory of your earlier conversations:";
+
+pub struct MemoryPlugin;
+
+impl Plugin for MemoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(MemoryConfig::default());
+        app.insert_resource(MemoryCompactionTasks { tasks: HashMap::new() });
+        app.add_systems(Update, (compact_memories, apply_compacted_memories));
+    }
+}
+
+// When an npc's history grows past token_budget everything but the system prompt and the last keep_recent
+// messages gets summarized into a single memory message
+#[derive(Resource)]
+pub struct MemoryConfig {
+    pub token_budget: usize,
+    pub keep_recent: usize,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            token_budget: 4000,
+            keep_recent: 8,
+        }
+    }
+}
+
+// Summaries being generated, keyed by npc name
+#[derive(Resource)]
+struct MemoryCompactionTasks {
+    tasks: HashMap<String, Compaction>,
+}
+
+struct Compaction {
+    // End of the range of message_history the summary replaces
+    cut: usize,
+    // Npc::history_generation when the summary was started
+    generation: u32,
+    task: JoinHandle<Result<String, LlmError>>,
+}
+
+pub fn estimate_tokens(messages: &[ChatMessage]) -> usize {
+    messages.iter().map(|message| message.get_content().len() / CHARS_PER_TOKEN + 1).sum()
+}
+
+fn compact_memories(
+    npc_query: Query<&Npc>,
+    config: Res<MemoryConfig>,
+    mut compactions: ResMut<MemoryCompactionTasks>,
+    runtime: ResMut<TokioTasksRuntime>,
+) {
+    for npc in npc_query.iter() {
+        if compactions.tasks.contains_key(&npc.name) || estimate_tokens(&npc.message_history) <= config.token_budget {
+            continue;
+        }
+        // Index 0 is the original system prompt from Npc::new, it's never summarized
+        let cut = npc.message_history.len().saturating_sub(config.keep_recent);
+        if cut <= 2 {
+            continue;
+        }
+        let transcript = npc.message_history[1..cut]
+            .iter()
+            .map(|message| format!("{}: {}", role_name(&message.get_role()), message.get_content()))
+            .collect::<Vec<String>>()
+            .join("\n");
+        let llm = npc.llm.clone();
+        info!("Compacting the memory of {} ({} messages)", npc.name, cut - 1);
+
+        let task = runtime.spawn_background_task(|_ctx| async move {
+            let request = ChatRequest::new(vec![
+                ChatMessage::new(MessageRole::System, SUMMARY_PROMPT.to_string()),
+                ChatMessage::new(MessageRole::User, transcript),
+            ]);
+            Ok(llm.send_msg(&request).await?.get_message().get_content())
+        });
+        compactions.tasks.insert(npc.name.clone(), Compaction { cut, generation: npc.history_generation, task });
+    }
+}
+
+// Swaps the summarized messages for the memory. As long as the history generation is the same, messages were only
+// appended while the summary was generated, so everything before cut is still exactly what was summarized
+fn apply_compacted_memories(
+    mut npc_query: Query<&mut Npc>,
+    mut compactions: ResMut<MemoryCompactionTasks>,
+) {
+    compactions.tasks.retain(|npc_name, compaction| {
+        let status = block_on(future::poll_once(&mut compaction.task));
+        let retain = status.is_none();
+
+        match status {
+            Some(Ok(Ok(summary))) => {
+                if let Some(mut npc) = npc_query.iter_mut().find(|npc| &npc.name == npc_name) {
+                    if npc.history_generation != compaction.generation || compaction.cut > npc.message_history.len() {
+                        info!("Dropped an outdated memory summary of {}, its history changed in the meantime", npc_name);
+                    } else {
+                        let memory = ChatMessage::new(MessageRole::System, format!("{} {}", MEMORY_PREFIX, summary));
+                        npc.message_history.splice(1..compaction.cut, [memory]);
+                    }
+                }
+            }
+            Some(Ok(Err(err))) => warn!("Could not compact the memory of {}: {}", npc_name, err),
+            _ => {}
+        }
+        retain
+    });
+}
+
+fn role_name(role: &MessageRole) -> &'static str {
+    match role {
+        MessageRole::System => "Game",
+        MessageRole::User => "Player",
+        MessageRole::Assistant => "You",
+    }
+}
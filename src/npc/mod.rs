@@ -1,3 +1,10 @@
 pub mod npc_plugin;
 pub mod talk;
-pub mod npc;
\ No newline at end of file
+pub mod npc;
+pub mod npc_definition;
+pub mod memory;
+pub mod npc_conversation;
+pub mod relationship;
+pub mod follow;
+pub mod shop;
+pub mod resupply;
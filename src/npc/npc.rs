@@ -1,36 +1,73 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 use std::vec;
 use bevy::prelude::{Bundle, Component};
-use crate::{communication::{Communicator}, llm::{send_msg}};
+use crate::{communication::{Communicator}, llm::{LlmBackend, LlmError, TokenSender}};
 use crate::character::{Character, CharacterTrait};
 use crate::communication::{ChatMessage, ChatRequest, MessageRole};
-use crate::item::Item;
+use crate::trade::DEFAULT_MIN_MARGIN_PERCENT;
+use crate::tts::VoiceProfile;
 
 #[derive(Component, Clone)]
 pub struct Npc {
     pub(crate) name: String,
     pub(crate) occupation: String,
     pub(crate) backstory: String,
-    pub(crate) items: HashMap<Item, i32>,
     pub(crate) message_history: Vec<ChatMessage>,
-    //pub(crate) http_client: &'static reqwest::Client,
+    // Bumped whenever messages are taken out of message_history instead of appended, e.g. when a save is loaded.
+    // Memory summaries started in an older generation no longer match the history and are dropped
+    pub(crate) history_generation: u32,
+    // The model this npc talks through, shared between all npcs using the same backend
+    pub(crate) llm: Arc<dyn LlmBackend>,
+    // How the npc sounds when its lines are spoken
+    pub(crate) voice: VoiceProfile,
+    // How much more the npc wants to get than it gives in a trade, in percent of what it gives
+    pub(crate) min_margin_percent: i32,
 }
 
+// Used when an npc definition doesn't bring its own prompt_template
+pub const DEFAULT_PROMPT_TEMPLATE: &str = "You are a NPC in a RPG game. Your name is {name} and you are a {occupation}. This is your backstory: {backstory}.";
+
 impl Npc {
-    pub(crate) fn new(name: &str, occupation: &str, backstory: &str) -> Npc {
-        let npc_init = format!("You are a NPC in a RPG game. Your name is {name} and you are a {occupation}. This is your backstory: {backstory}.\n\
+    pub(crate) fn new(name: &str, occupation: &str, backstory: &str, prompt_template: &str, llm: Arc<dyn LlmBackend>) -> Npc {
+        Npc {
+            message_history: vec![ChatMessage::new(MessageRole::System, build_system_prompt(name, occupation, backstory, prompt_template))],
+            history_generation: 0,
+            name: name.to_string(),
+            occupation: occupation.to_string(),
+            backstory: backstory.to_string(),
+            llm,
+            voice: VoiceProfile::default(),
+            min_margin_percent: DEFAULT_MIN_MARGIN_PERCENT,
+        }
+    }
+
+    // Swaps in a new persona while keeping the conversation going, used when the npc definition is edited.
+    // The name stays, relationships, quests, trades and saves all refer to the npc by it
+    pub(crate) fn set_persona(&mut self, occupation: &str, backstory: &str, prompt_template: &str) {
+        self.occupation = occupation.to_string();
+        self.backstory = backstory.to_string();
+        self.message_history[0] = ChatMessage::new(MessageRole::System, build_system_prompt(&self.name, occupation, backstory, prompt_template));
+    }
+}
+
+fn build_system_prompt(name: &str, occupation: &str, backstory: &str, prompt_template: &str) -> String {
+    let persona = prompt_template
+        .replace("{name}", name)
+        .replace("{occupation}", occupation)
+        .replace("{backstory}", backstory);
+    format!("{persona}\n\
         The communication between you as a npc and the player will be done using json objects. This is generally how one would look: \n{}, \n{}\n{}\n{}\n
         ", r#"
         {
             "sender_id": "Bob",
             "receiver_id": "Hank",
-            "message": "Deal! 50 Gold Coins for a Steel Sword sounds good to me.",
+            "message": "Deal! 55 Gold Coins for a Steel Sword sounds good to me.",
             "actions": [
                 {
-                    "Give":
+                    "Offer":
                     {
-                        "item": "Gold Coin",
-                        "amount": 50
+                        "give": [{ "item": "Gold Coin", "amount": 55 }],
+                        "want": [{ "item": "Steel Sword", "amount": 1 }]
                     }
                 }
             ]
@@ -45,101 +82,115 @@ impl Npc {
                     "item": "Gold Coin",
                     "amount": 30
                 }
-            ]
+            ],
+            "relationship": {
+                "with": "Bob",
+                "trust": 12,
+                "friendliness": 25,
+                "balance": 0,
+                "discount_percent": 3
+            },
+            "quests": {
+                "offers": [
+                    {
+                        "id": "bread_for_the_forge",
+                        "title": "Bread for the Forge",
+                        "description": "Hank has been at the forge all day and forgot to eat. Bring him some bread from the inn.",
+                        "objective": "bring 2 bread",
+                        "reward": "10 gold_coin"
+                    }
+                ],
+                "taken": [
+                    {
+                        "id": "a_friendly_face",
+                        "title": "A Friendly Face",
+                        "objective": "talk to Greta",
+                        "status": "Active",
+                        "objective_met": true
+                    }
+                ]
+            },
+            "time": {
+                "day": 3,
+                "weekday": "Thursday",
+                "time": "17:45",
+                "daytime": true
+            },
+            "shop": {
+                "open": true,
+                "hours": "09:00-18:00",
+                "days": ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"]
+            },
+            "stock": {
+                "running_low": ["steel_sword"],
+                "supply_location": "storehouse",
+                "resupplying": false
+            }
         }
         "#, "
          The first object is the request that the user sends you.
-         You have to replace the values for these keys with the appropriate values. For example in the example above a player agrees to buy a Steel Sword from you for 50 Gold Coins.\n\
-         In the message he lets this know and in the list of actions he triggers the Give action with the parameters specifying which item he sends to you and the amount of items.
+         You have to replace the values for these keys with the appropriate values. For example in the example above a player agrees to buy a Steel Sword from you for 55 Gold Coins.\n\
+         In the message he lets this know and in the list of actions he makes an Offer with what he gives you and what he wants from you in return. The game holds his side until you answer.
          The second object is passed to you by the game and lets you know what items you as the NPC currently have. You can only give items that you have (enough of).
-         You would respond to this with a message to your liking and a Give action as well. For example:
+         It also tells you what you think of the one talking to you. Trust and friendliness go from -100 to 100. Balance is the value in gold of what they gave you minus what you gave them, when it's negative they owe you. Lower your prices by discount_percent for them, loyal customers deserve a better deal.
+         Quests are the tasks you can give them (offers) and the ones they already agreed to do for you (taken). You can't make up quests, only offer the ones listed.
+         Time is the current time in the game. Shop is null when you don't run a shop. When your shop is closed you can't trade, so tell them when to come back instead, e.g. tomorrow morning.
+         Stock is null when you don't sell anything, otherwise it lists the items you're running low on.
+         You would respond to this with a message to your liking and an Accept action, or Reject when the deal isn't good enough. For example:
         ", r#"
         {
         "sender_id": "Hank",
         "receiver_id": "Bob",
         "message": "It was a pleasure doing business with you!",
         "actions": [
-            {
-                "Give":
-                {
-                    "item": "Steel Sword",
-                    "amount": 1
-                }
-            }
-        ]
+            "Accept"
+        ],
+        "sentiment": "positive"
         }
         "#,
-        "As you can see you don't send the second object (your inventory). The game will update your inventory for you. Only communicate with one json object and never put any more text before or after the object or it will fail! \
+        "Accept swaps the items of their offer, you can only accept offers that earn you at least your margin on top of the price of what you give. Their discount comes out of your margin, you never sell below price. \
+        If it doesn't the game tells you how much you need, so Reject and make your own Offer instead, e.g. {\"Offer\": {\"give\": [{\"item\": \"Steel Sword\", \"amount\": 1}], \"want\": [{\"item\": \"Gold Coin\", \"amount\": 55}]}}. \
+        Only they can accept your offer. {\"Give\": {\"item\": \"Bread\", \"amount\": 1}} hands over a small gift without anything in return, never give away goods or money worth more than a few coins. \
+        Besides trading there are more actions. {\"Follow\": {\"target\": \"Bob\"}} makes you walk after Bob, e.g. when you show him the way, until you send \"StopFollowing\" as an action. \
+        {\"Move\": {\"destination\": \"inn\"}} makes you walk to a named place, {\"Move\": {\"destination\": {\"x\": 3.0, \"z\": -2.0}}} to a spot on the ground. If the place doesn't exist the game will tell you which places do. \
+        {\"GiveQuest\": {\"quest\": \"bread_for_the_forge\"}} gives them one of your offers once they agree to do it. When objective_met of a taken quest is true and they come back to you, send {\"CompleteQuest\": {\"quest\": \"a_friendly_face\"}} to hand out the reward, the game takes the fetched items from them and pays the reward from your inventory. \
+        \"Open\" and \"Close\" open or close your shop, e.g. closing early or staying open a bit longer for a good customer. The game opens and closes it on your opening hours as well. \
+        \"Resupply\" sends you to your supply location to restock what you sell, you can't trade until you're back. Do it when you're running low. \
+        Sentiment is how their message made you feel about them: positive, neutral or negative. \
+        As you can see you don't send the second object (your inventory). The game will update your inventory for you. Only communicate with one json object and never put any more text before or after the object or it will fail! \
         Also don't add ```json before and ``` after the object. Just send the object only. So the first character will always be { and the last character you send will always be }. The content of the message field should be one long string without line breaks or newlines. We will parse it on the game side\
         also sometimes the player will end the conversation naturally and you can choose to not respond to it anymore as this is more natural. If you want to do this just send an empty string for message",
-        );
-        Npc {
-            message_history: vec![ChatMessage::new(MessageRole::System, npc_init)],
-            name: name.to_string(),
-            occupation: occupation.to_string(),
-            backstory: backstory.to_string(),
-            items: HashMap::new(),
-
-        }
-    }
+    )
 }
 
 
 impl CharacterTrait for Npc {
-    fn set_items(&mut self, items: HashMap<Item, i32>) {
-        self.items = items;
-    }
-
-    //noinspection DuplicatedCode
-    fn add_item(&mut self, item: Item, amount: i32) {
-        for (key, value) in &mut self.items {
-            if key.name == item.name {
-                *value += amount;
-                return;
-            }
-        }
-        self.items.insert(item, amount);
-    }
-
-    //noinspection DuplicatedCode
-    fn remove_item(&mut self, item: Item, amount: i32) -> bool {
-        // If player does not have the item, return false
-        if self.items.iter().position(|(key, _value)| key.name == item.name).is_none() {
-            return false;
-        }
-        self.items = self.items.clone().into_iter().filter_map(|(key, value)| {
-            if key.name != item.name {
-                return Some((key.to_owned(), value));
-            }
-            if value - amount <= 0 {
-                return None;
-            }
-            Some((key.to_owned(), value - amount))
-        }).collect();
-        true
-    }
-
-    fn get_items(&self) -> &HashMap<Item, i32> {
-        &self.items
+    fn get_name(&self) -> &str {
+        &self.name
     }
 
     fn print_self(&self) {
-        println!("{}", self.name);
-        for (item, amount) in &self.items {
-            println!("{}: {}", item.name, amount);
-        }
+        println!("{} the {}", self.name, self.occupation);
     }
 }
 
 impl Communicator for Npc {
-    async fn talk(&mut self, message: ChatMessage) -> String {
+    async fn talk(&mut self, message: ChatMessage) -> Result<String, LlmError> {
         // Push user's message into the history
         self.message_history.push(message);
         let request = ChatRequest::new(self.message_history.clone());
-        let response = send_msg(&request).await.unwrap();
+        let response = self.llm.send_msg(&request).await?;
         // Push models response message into the history
         self.message_history.push(response.get_message());
         // Return the response message
-        response.get_message().get_content()
+        Ok(response.get_message().get_content())
+    }
+
+    async fn talk_streaming(&mut self, message: ChatMessage, tokens: TokenSender) -> Result<String, LlmError> {
+        self.message_history.push(message);
+        let request = ChatRequest::new(self.message_history.clone()).with_stream(true);
+        let response = self.llm.stream_msg(&request, tokens).await?;
+        self.message_history.push(response.get_message());
+        Ok(response.get_message().get_content())
     }
 }
\ No newline at end of file
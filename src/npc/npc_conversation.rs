@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::system::SystemParam;
+use bevy::log::{info, warn};
+use bevy::prelude::{Entity, Has, IntoSystemConfigs, Query, Res, ResMut, Resource, Time, Timer, TimerMode, Transform};
+use bevy::tasks::{block_on, futures_lite::future};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::task::JoinHandle;
+use crate::{Interaction, NpcContext};
+use crate::action_executor::ExecuteActionsEvent;
+use crate::communication::{ChatMessage, MessageRole};
+use crate::clock::{GameClock, TimeContext};
+use crate::guard::{ReplyGuard, ReplyPolicy};
+use crate::interaction_parser::{request_interaction, ParseFailure};
+use crate::inventory::Inventory;
+use crate::npc::npc::Npc;
+use crate::npc::resupply::{Resupplying, Stock, StockContext};
+use crate::npc::shop::{Shop, ShopContext};
+use crate::npc::relationship::{Relationships, RelationshipContext, SentimentEvent};
+use crate::player::actions_plugin::{ActiveConversation, DialogueEvents};
+use crate::player::speech_bubble_plugin::SpeakEvent;
+
+// Lets npcs that stand close to each other have a short chat. Every line is generated by the speaking npc's own model,
+// so both sides stay in character and remember the conversation
+pub struct NpcConversationPlugin;
+
+impl Plugin for NpcConversationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(NpcConversationConfig::default());
+        app.insert_resource(NpcConversations { active: Vec::new(), last_talked: HashMap::new(), last_started: None });
+        app.add_systems(Update, (start_npc_conversations, continue_npc_conversations).chain());
+    }
+}
+
+// Limits on npc conversations, every line is a request to the model
+#[derive(Resource)]
+pub struct NpcConversationConfig {
+    // How close two npcs have to be to start talking, they stop when they get twice as far apart
+    pub distance: f32,
+    // Most lines in one conversation, both sides counted
+    pub max_turns: u32,
+    // Most conversations going on at the same time
+    pub max_active: usize,
+    // Seconds between two conversations starting anywhere
+    pub min_interval: f32,
+    // Seconds before the same two npcs talk again
+    pub pair_cooldown: f32,
+    // Seconds between the lines of a conversation so the bubbles can be read
+    pub turn_delay: f32,
+}
+
+impl Default for NpcConversationConfig {
+    fn default() -> Self {
+        Self {
+            distance: 3.0,
+            max_turns: 6,
+            max_active: 1,
+            min_interval: 30.0,
+            pair_cooldown: 300.0,
+            turn_delay: 4.0,
+        }
+    }
+}
+
+#[derive(Resource)]
+struct NpcConversations {
+    active: Vec<NpcConversation>,
+    // When two npcs last started talking, keyed by their names in alphabetical order
+    last_talked: HashMap<(String, String), f32>,
+    last_started: Option<f32>,
+}
+
+impl NpcConversations {
+    // Npcs the player is talking to, that are already talking or that are off resupplying are busy
+    fn is_busy(&self, npc: Entity, resupplying: bool, player_conversation: &ActiveConversation) -> bool {
+        resupplying || player_conversation.is_with(npc) || self.active.iter().any(|conversation| conversation.involves(npc))
+    }
+}
+
+struct NpcConversation {
+    // The npc whose turn it is
+    speaker: Entity,
+    listener: Entity,
+    turns: u32,
+    // What the listener said last, None before the opening line
+    last_message: Option<String>,
+    task: Option<JoinHandle<Result<Interaction, ParseFailure>>>,
+    // Runs between two lines
+    delay: Timer,
+    ended: bool,
+}
+
+impl NpcConversation {
+    fn involves(&self, entity: Entity) -> bool {
+        self.speaker == entity || self.listener == entity
+    }
+
+    fn end(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        self.ended = true;
+    }
+}
+
+// The conversations going on, and what limits and interrupts them
+#[derive(SystemParam)]
+struct Conversations<'w> {
+    config: Res<'w, NpcConversationConfig>,
+    active: ResMut<'w, NpcConversations>,
+    player_conversation: Res<'w, ActiveConversation>,
+}
+
+fn pair_key(a: &str, b: &str) -> (String, String) {
+    if a < b {
+        (a.to_string(), b.to_string())
+    } else {
+        (b.to_string(), a.to_string())
+    }
+}
+
+// Pairs up two idle npcs that are close to each other, as long as the rate limits allow it
+fn start_npc_conversations(
+    time: Res<Time>,
+    config: Res<NpcConversationConfig>,
+    mut conversations: ResMut<NpcConversations>,
+    player_conversation: Res<ActiveConversation>,
+    npc_query: Query<(Entity, &Npc, &Transform, Has<Resupplying>)>,
+) {
+    let now = time.elapsed_seconds();
+    if conversations.active.len() >= config.max_active {
+        return;
+    }
+    if conversations.last_started.is_some_and(|started| now - started < config.min_interval) {
+        return;
+    }
+    let idle = npc_query
+        .iter()
+        .filter(|(entity, _, _, resupplying)| !conversations.is_busy(*entity, *resupplying, &player_conversation))
+        .map(|(entity, npc, transform, _)| (entity, npc, transform))
+        .collect::<Vec<_>>();
+    for (i, (a, a_npc, a_transform)) in idle.iter().enumerate() {
+        for (b, b_npc, b_transform) in &idle[i + 1..] {
+            if a_transform.translation.distance(b_transform.translation) > config.distance {
+                continue;
+            }
+            let key = pair_key(&a_npc.name, &b_npc.name);
+            if conversations.last_talked.get(&key).is_some_and(|talked| now - talked < config.pair_cooldown) {
+                continue;
+            }
+            info!("{} starts talking to {}", a_npc.name, b_npc.name);
+            conversations.last_talked.insert(key, now);
+            conversations.last_started = Some(now);
+            conversations.active.push(NpcConversation {
+                speaker: *a,
+                listener: *b,
+                turns: 0,
+                last_message: None,
+                task: None,
+                // The opening line doesn't have to wait
+                delay: Timer::from_seconds(0.0, TimerMode::Once),
+                ended: false,
+            });
+            return;
+        }
+    }
+}
+
+// Asks the speaking npc for its next line and hands the turn to the other npc once it's there
+fn continue_npc_conversations(
+    time: Res<Time>,
+    clock: Res<GameClock>,
+    runtime: ResMut<TokioTasksRuntime>,
+    mut conversations: Conversations,
+    mut npc_query: Query<(&mut Npc, &Transform, &Inventory, &Relationships, Option<&Shop>, Option<&Stock>, Has<Resupplying>)>,
+    mut dialogue: DialogueEvents,
+    mut reply_guard: ReplyGuard,
+) {
+    let Conversations { config, active: conversations, player_conversation } = &mut conversations;
+    for conversation in conversations.active.iter_mut() {
+        let (Ok((listener, listener_transform, .., listener_away)), Ok((_, speaker_transform, .., speaker_away))) = (npc_query.get(conversation.listener), npc_query.get(conversation.speaker)) else {
+            conversation.end();
+            continue;
+        };
+        let listener_name = listener.name.clone();
+        let listener_occupation = listener.occupation.clone();
+        // The player walking up to either of them, them walking apart or leaving to resupply ends the conversation
+        let walked_apart = speaker_transform.translation.distance(listener_transform.translation) > config.distance * 2.0;
+        let interrupted = player_conversation.npc.is_some_and(|npc| conversation.involves(npc));
+        if walked_apart || interrupted || listener_away || speaker_away {
+            conversation.end();
+            continue;
+        }
+
+        if let Some(task) = &mut conversation.task {
+            let Some(status) = block_on(future::poll_once(task)) else {
+                continue;
+            };
+            conversation.task = None;
+            let Ok((mut speaker, _, _, _, shop, stock, _)) = npc_query.get_mut(conversation.speaker) else {
+                conversation.end();
+                continue;
+            };
+            let mut interaction = match status {
+                Ok(Ok(interaction)) => interaction,
+                Ok(Err(failure)) => {
+                    warn!("{} could not respond to {}: {}", speaker.name, listener_name, failure);
+                    conversation.end();
+                    continue;
+                }
+                Err(err) => {
+                    warn!("Conversation task of {} failed: {}", speaker.name, err);
+                    conversation.end();
+                    continue;
+                }
+            };
+            // An empty message is the npc's way of ending the conversation
+            if interaction.message.trim().is_empty() {
+                conversation.end();
+                continue;
+            }
+            // Npcs can talk each other into things just like players can, only players take quests
+            let said = conversation.last_message.clone().unwrap_or_default();
+            let policy = ReplyPolicy::new(&reply_guard.guard, &speaker, &listener_name, &said, (shop.is_some(), stock.is_some()), &Default::default());
+            reply_guard.check_reply(&mut interaction, &policy);
+            speaker.message_history.push(ChatMessage::new(MessageRole::Assistant, interaction.message.clone()));
+            dialogue.on_sentiment.send(SentimentEvent { npc: speaker.name.clone(), towards: listener_name.clone(), sentiment: interaction.sentiment });
+            dialogue.on_speak.send(SpeakEvent { id: speaker.name.clone(), text: interaction.message.clone() });
+            dialogue.on_execute_actions.send(ExecuteActionsEvent {
+                sender_id: speaker.name.clone(),
+                receiver_id: listener_name,
+                actions: interaction.actions,
+            });
+            conversation.last_message = Some(interaction.message);
+            conversation.turns += 1;
+            if conversation.turns >= config.max_turns {
+                conversation.end();
+                continue;
+            }
+            (conversation.speaker, conversation.listener) = (conversation.listener, conversation.speaker);
+            conversation.delay = Timer::from_seconds(config.turn_delay, TimerMode::Once);
+            continue;
+        }
+
+        if !conversation.delay.tick(time.delta()).finished() {
+            continue;
+        }
+        let Ok((mut speaker, _, inventory, relationships, shop, stock, resupplying)) = npc_query.get_mut(conversation.speaker) else {
+            conversation.end();
+            continue;
+        };
+        let message = match &conversation.last_message {
+            Some(message) => message.clone(),
+            None => format!("Game: {} the {} is standing near you. Start a short conversation with them.", listener_name, listener_occupation),
+        };
+        let remembered = match &conversation.last_message {
+            Some(message) => format!("{}: {}", listener_name, message),
+            None => message.clone(),
+        };
+        speaker.message_history.push(ChatMessage::new(MessageRole::User, remembered));
+
+        let context = NpcContext {
+            npc_inventory: inventory.items().clone(),
+            relationship: RelationshipContext::new(&listener_name, &relationships.get(&listener_name)),
+            // Only players take quests
+            quests: Default::default(),
+            time: TimeContext::new(&clock),
+            shop: shop.map(ShopContext::new),
+            stock: stock.map(|stock| StockContext::new(stock, inventory, resupplying)),
+        };
+        let interaction = Interaction {
+            sender_id: listener_name,
+            receiver_id: speaker.name.clone(),
+            message,
+            actions: vec![],
+            sentiment: Default::default(),
+        };
+        let mut js = serde_json::to_string(&interaction).unwrap_or_default();
+        js.push_str(&serde_json::to_string(&context).unwrap_or_default());
+        let mut npc_clone = speaker.clone();
+        conversation.task = Some(runtime.spawn_background_task(|_ctx| async move {
+            // Nobody watches npc conversations being typed, the tokens are dropped
+            let (tokens, _) = unbounded_channel();
+            request_interaction(&mut npc_clone, ChatMessage::new(MessageRole::User, js), tokens).await
+        }));
+    }
+    conversations.active.retain(|conversation| !conversation.ended);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversations(talking: &[(Entity, Entity)]) -> NpcConversations {
+        let active = talking
+            .iter()
+            .map(|(speaker, listener)| NpcConversation {
+                speaker: *speaker,
+                listener: *listener,
+                turns: 0,
+                last_message: None,
+                task: None,
+                delay: Timer::from_seconds(0.0, TimerMode::Once),
+                ended: false,
+            })
+            .collect();
+        NpcConversations { active, last_talked: HashMap::new(), last_started: None }
+    }
+
+    #[test]
+    fn npcs_the_player_talks_to_are_busy_until_the_conversation_ends() {
+        let npc = Entity::from_raw(1);
+        let mut player_conversation = ActiveConversation { npc: Some(npc) };
+        let conversations = conversations(&[]);
+        assert!(conversations.is_busy(npc, false, &player_conversation));
+        assert!(!conversations.is_busy(Entity::from_raw(2), false, &player_conversation));
+        player_conversation.npc = None;
+        assert!(!conversations.is_busy(npc, false, &player_conversation));
+    }
+
+    #[test]
+    fn talking_or_resupplying_npcs_are_busy() {
+        let (a, b, c) = (Entity::from_raw(1), Entity::from_raw(2), Entity::from_raw(3));
+        let conversations = conversations(&[(a, b)]);
+        let player_conversation = ActiveConversation::default();
+        assert!(conversations.is_busy(a, false, &player_conversation));
+        assert!(conversations.is_busy(b, false, &player_conversation));
+        assert!(!conversations.is_busy(c, false, &player_conversation));
+        assert!(conversations.is_busy(c, true, &player_conversation));
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt::{Display, Formatter};
+use bevy::asset::{io::Reader, Asset, AssetLoader, AsyncReadExt, LoadContext};
+use bevy::color::Color;
+use bevy::reflect::TypePath;
+use serde::{Deserialize, Serialize};
+use crate::npc::resupply::StockDefinition;
+use crate::npc::shop::OpeningHours;
+use crate::trade::DEFAULT_MIN_MARGIN_PERCENT;
+use crate::tts::VoiceProfile;
+
+// Describes a single npc, loaded from assets/npcs/*.npc.ron or *.npc.json
+// Every file spawns one npc, editing the file while the game runs updates the npc's persona
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct NpcDefinition {
+    pub name: String,
+    pub occupation: String,
+    pub backstory: String,
+    // Items the npc starts with, referenced by item id
+    #[serde(default)]
+    pub inventory: Vec<InventoryEntry>,
+    // Optional limits on how many items and how much weight the npc can carry
+    #[serde(default)]
+    pub capacity: Option<i32>,
+    #[serde(default)]
+    pub max_weight: Option<u32>,
+    pub position: (f32, f32, f32),
+    #[serde(default)]
+    pub appearance: Appearance,
+    // How the npc sounds, e.g. voice: (speaker: Some("voices/hank.wav"), speed: 0.9, pitch: -2.0, emotion: Some("calm"))
+    // Anything left out uses the TTS default
+    #[serde(default)]
+    pub voice: VoiceProfile,
+    // Opening hours of the npc's shop, e.g. shop: Some((open: 9, close: 18)). No shop when left out
+    #[serde(default)]
+    pub shop: Option<OpeningHours>,
+    // What the npc sells and wants to keep in stock, the Resupply action tops it up
+    #[serde(default)]
+    pub stock: Option<StockDefinition>,
+    // The least the npc wants to earn on a trade, in percent of the value it gives, e.g. 20
+    #[serde(default = "default_min_margin_percent")]
+    pub min_margin_percent: i32,
+    // Introduces the npc to the model. {name}, {occupation} and {backstory} are filled in,
+    // the instructions on how to respond are always added by the game
+    #[serde(default)]
+    pub prompt_template: Option<String>,
+}
+
+impl NpcDefinition {
+    // What serde can't check on its own
+    fn validate(&self) -> Result<(), String> {
+        if let Some(hours) = &self.shop {
+            hours.validate().map_err(|err| format!("shop: {}", err))?;
+        }
+        Ok(())
+    }
+}
+
+fn default_min_margin_percent() -> i32 {
+    DEFAULT_MIN_MARGIN_PERCENT
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InventoryEntry {
+    pub item: String,
+    pub amount: i32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum Appearance {
+    // Plain cube in the given srgb color
+    Color(f32, f32, f32),
+    // Path to a gltf file, its first scene is used
+    Model(String),
+}
+
+impl Default for Appearance {
+    fn default() -> Self {
+        Appearance::Color(0.0, 0.0, 1.0)
+    }
+}
+
+impl Appearance {
+    pub fn color(&self) -> Color {
+        match self {
+            Appearance::Color(r, g, b) => Color::srgb(*r, *g, *b),
+            Appearance::Model(_) => Color::WHITE,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct NpcDefinitionLoader;
+
+#[derive(Debug)]
+pub enum NpcDefinitionError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Json(serde_json::Error),
+    Invalid(String),
+}
+
+impl Display for NpcDefinitionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NpcDefinitionError::Io(err) => write!(f, "could not read npc definition: {}", err),
+            NpcDefinitionError::Ron(err) => write!(f, "could not parse npc definition: {}", err),
+            NpcDefinitionError::Json(err) => write!(f, "could not parse npc definition: {}", err),
+            NpcDefinitionError::Invalid(err) => write!(f, "invalid npc definition: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for NpcDefinitionError {}
+
+impl AssetLoader for NpcDefinitionLoader {
+    type Asset = NpcDefinition;
+    type Settings = ();
+    type Error = NpcDefinitionError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.map_err(NpcDefinitionError::Io)?;
+        let is_json = load_context.path().extension().is_some_and(|extension| extension == "json");
+        let definition = if is_json {
+            serde_json::from_slice::<NpcDefinition>(&bytes).map_err(NpcDefinitionError::Json)?
+        } else {
+            ron::de::from_bytes::<NpcDefinition>(&bytes).map_err(NpcDefinitionError::Ron)?
+        };
+        definition.validate().map_err(NpcDefinitionError::Invalid)?;
+        Ok(definition)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["npc.ron", "npc.json"]
+    }
+}
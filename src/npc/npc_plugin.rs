@@ -1,39 +1,181 @@
 use crate::character::spawn_character_entity;
-use crate::npc::npc::Npc;
-use bevy::prelude::TextBundle;
-use bevy::{app::{App, Plugin, Startup}, asset::Assets, color::Color, pbr::StandardMaterial, prelude::{Commands, Mesh, ResMut}};
-use bevy::text::Text;
+use crate::clock::GameClock;
+use crate::inventory::Inventory;
+use crate::item_registry::ItemRegistry;
+use crate::llm::Llm;
+use crate::npc::npc::{Npc, DEFAULT_PROMPT_TEMPLATE};
+use crate::npc::relationship::Relationships;
+use crate::npc::resupply::Stock;
+use crate::npc::shop::Shop;
+use crate::npc::npc_definition::{Appearance, NpcDefinition, NpcDefinitionLoader};
+use bevy::asset::{AssetEvent, AssetId, AssetServer, Handle, LoadedFolder, RecursiveDependencyLoadState};
+use bevy::ecs::system::SystemParam;
+use bevy::gltf::GltfAssetLabel;
+use bevy::log::warn;
+use bevy::prelude::{default, AssetApp, BuildChildren, Component, Entity, EventReader, Query, Res, Resource, SceneBundle, Transform, Update};
+use bevy::{app::{App, Plugin, Startup}, asset::Assets, pbr::StandardMaterial, prelude::{Commands, Mesh, ResMut}};
 
 pub struct NpcPlugin;
 
 impl Plugin for NpcPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_npcs);
+        app.init_asset::<NpcDefinition>();
+        app.init_asset_loader::<NpcDefinitionLoader>();
+        app.add_systems(Startup, load_npc_definitions);
+        app.add_systems(Update, spawn_npcs);
     }
 }
 
+// Keeps the npc definitions loaded (and watched for changes) for as long as the game runs
+#[derive(Resource)]
+struct NpcDefinitions {
+    folder: Handle<LoadedFolder>,
+}
+
+// Tells whether the npc of every definition is there yet
+#[derive(SystemParam)]
+pub struct NpcRoster<'w, 's> {
+    asset_server: Res<'w, AssetServer>,
+    npc_definitions: Option<Res<'w, NpcDefinitions>>,
+    definitions: Res<'w, Assets<NpcDefinition>>,
+    source_query: Query<'w, 's, &'static NpcSource>,
+}
+
+impl NpcRoster<'_, '_> {
+    // Npcs show up a frame after their definition loaded. Definitions that failed to load don't count
+    pub fn complete(&self) -> bool {
+        let loaded = self.npc_definitions.as_ref().is_some_and(|npc_definitions| {
+            matches!(
+                self.asset_server.get_recursive_dependency_load_state(&npc_definitions.folder),
+                Some(RecursiveDependencyLoadState::Loaded | RecursiveDependencyLoadState::Failed)
+            )
+        });
+        loaded && self.definitions.ids().all(|id| self.source_query.iter().any(|source| source.0 == id))
+    }
+}
+
+// Links an npc to the definition it was spawned from
+#[derive(Component)]
+pub struct NpcSource(pub AssetId<NpcDefinition>);
+
+fn load_npc_definitions(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(NpcDefinitions { folder: asset_server.load_folder("npcs") });
+}
+
+// Everything that goes into spawning an npc from its definition
+#[derive(SystemParam)]
+struct NpcSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    asset_server: Res<'w, AssetServer>,
+    llm: Res<'w, Llm>,
+    item_registry: Res<'w, ItemRegistry>,
+    clock: Res<'w, GameClock>,
+}
+
+// Spawns an npc for every definition that finishes loading and updates the persona of npcs whose definition changed
 fn spawn_npcs(
-    commands: Commands,
-    meshes: ResMut<Assets<Mesh>>,
-    materials: ResMut<Assets<StandardMaterial>>,
+    mut spawner: NpcSpawner,
+    definitions: Res<Assets<NpcDefinition>>,
+    mut on_definition: EventReader<AssetEvent<NpcDefinition>>,
+    mut npc_query: Query<(Entity, &mut Npc, &NpcSource, Option<&mut Stock>)>,
 ) {
-    spawn_npc(commands, meshes, materials);
+    for event in on_definition.read() {
+        match event {
+            AssetEvent::Added { id } => {
+                if let Some(definition) = definitions.get(*id) {
+                    spawner.spawn(*id, definition);
+                }
+            }
+            AssetEvent::Modified { id } => {
+                let Some(definition) = definitions.get(*id) else {
+                    continue;
+                };
+                for (entity, mut npc, source, stock) in npc_query.iter_mut() {
+                    if source.0 == *id {
+                        if npc.name != definition.name {
+                            warn!("{} can't be renamed to {} while the game runs, restart to use the new name", npc.name, definition.name);
+                        }
+                        npc.set_persona(&definition.occupation, &definition.backstory, prompt_template(definition));
+                        npc.voice = definition.voice.clone();
+                        npc.min_margin_percent = definition.min_margin_percent;
+                        let mut commands = spawner.commands.entity(entity);
+                        match &definition.shop {
+                            Some(hours) => commands.insert(Shop::new(hours.clone(), &spawner.clock)),
+                            None => commands.remove::<Shop>(),
+                        };
+                        match (&definition.stock, stock) {
+                            (Some(definition), Some(mut stock)) => stock.set_definition(definition),
+                            (Some(definition), None) => {
+                                commands.insert(Stock::new(definition));
+                            }
+                            (None, _) => {
+                                commands.remove::<Stock>();
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
-fn spawn_npc(
-    mut commands: Commands,
-    meshes: ResMut<Assets<Mesh>>,
-    materials: ResMut<Assets<StandardMaterial>>,
-) {
-    let character = spawn_character_entity(
-        &mut commands,
-        meshes,
-        materials,
-        Color::srgb(0.0, 0.0, 1.0),
-        (0.0, 5.0, 2.0),
-    );
-    commands
-        .entity(character)
-        .insert(Npc::new("Hank", "Blacksmith", "Hank is a well respected blacksmith in the Kingdom of Veldora"));
-
-}
\ No newline at end of file
+impl NpcSpawner<'_, '_> {
+    fn spawn(&mut self, id: AssetId<NpcDefinition>, definition: &NpcDefinition) {
+        let NpcSpawner { commands, meshes, materials, asset_server, llm, item_registry, clock } = self;
+        let character = spawn_character_entity(
+            commands,
+            meshes,
+            materials,
+            definition.appearance.color(),
+            definition.position,
+        );
+
+        let mut npc = Npc::new(&definition.name, &definition.occupation, &definition.backstory, prompt_template(definition), llm.backend.clone());
+        npc.voice = definition.voice.clone();
+        npc.min_margin_percent = definition.min_margin_percent;
+        let mut inventory = Inventory::new(definition.capacity, definition.max_weight);
+        for entry in &definition.inventory {
+            let Some(item) = item_registry.get(&entry.item) else {
+                warn!("{} starts with unknown item '{}'", definition.name, entry.item);
+                continue;
+            };
+            if let Err(err) = inventory.add(item, entry.amount) {
+                warn!("{} can't start with {} {}: {}", definition.name, entry.amount, entry.item, err);
+            }
+        }
+
+        commands.entity(character).insert((npc, inventory, Relationships::default(), NpcSource(id)));
+        if let Some(hours) = &definition.shop {
+            commands.entity(character).insert(Shop::new(hours.clone(), clock));
+        }
+        if let Some(stock) = &definition.stock {
+            for target in &stock.targets {
+                if item_registry.get(&target.item).is_none() {
+                    warn!("{} stocks unknown item '{}'", definition.name, target.item);
+                }
+            }
+            commands.entity(character).insert(Stock::new(stock));
+        }
+
+        // Swap the cube for the model, the cube's collider stays
+        if let Appearance::Model(path) = &definition.appearance {
+            commands
+                .entity(character)
+                .remove::<Handle<Mesh>>()
+                .with_children(|parent| {
+                    parent.spawn(SceneBundle {
+                        scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset(path.clone())),
+                        transform: Transform::from_xyz(0.0, -0.5, 0.0),
+                        ..default()
+                    });
+                });
+        }
+    }
+}
+
+fn prompt_template(definition: &NpcDefinition) -> &str {
+    definition.prompt_template.as_deref().unwrap_or(DEFAULT_PROMPT_TEMPLATE)
+}
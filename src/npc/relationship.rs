@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use bevy::app::{App, Plugin, Update};
+use bevy::log::debug;
+use bevy::prelude::{Component, Event, EventReader, Query, Res};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::Action;
+use crate::action_executor::ActionOutcomeEvent;
+use crate::item_registry::ItemRegistry;
+use crate::npc::npc::Npc;
+use crate::trade::TradeCompletedEvent;
+
+// Trust and friendliness run from -100 to 100
+const MAX_AFFINITY: i32 = 100;
+// How much a single message moves friendliness
+const SENTIMENT_STEP: i32 = 5;
+// Trust gained per completed exchange where the character doesn't owe the npc anything
+const TRADE_TRUST: i32 = 2;
+// Trust lost when a character promises something they can't give
+const FAILED_GIVE_TRUST: i32 = -5;
+// Every this much gold traded is worth a percent of discount, up to MAX_DISCOUNT_PERCENT
+const GOLD_PER_DISCOUNT_PERCENT: i32 = 50;
+const MAX_DISCOUNT_PERCENT: i32 = 20;
+
+pub struct RelationshipPlugin;
+
+impl Plugin for RelationshipPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SentimentEvent>();
+        app.add_systems(Update, (update_from_actions, update_from_trades, update_from_sentiment));
+    }
+}
+
+// How the model felt about the message it answered, reported in every Interaction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Sentiment {
+    Negative,
+    #[default]
+    Neutral,
+    Positive,
+}
+
+// Emitted when an npc answered someone, carries the sentiment of its answer
+#[derive(Event)]
+pub struct SentimentEvent {
+    pub npc: String,
+    pub towards: String,
+    pub sentiment: Sentiment,
+}
+
+// What an npc thinks of one other character
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Relationship {
+    pub trust: i32,
+    pub friendliness: i32,
+    // Value in gold of what the character gave the npc minus what they got back.
+    // Positive when the npc owes them a favor, negative when they're in the npc's debt
+    pub balance: i32,
+    // Value in gold of everything the character ever gave the npc
+    pub traded: i32,
+}
+
+impl Relationship {
+    // Loyal, liked customers get a discount on the npc's prices
+    pub fn discount_percent(&self) -> i32 {
+        let loyalty = self.traded / GOLD_PER_DISCOUNT_PERCENT + (self.trust + self.friendliness) / 20;
+        loyalty.clamp(0, MAX_DISCOUNT_PERCENT)
+    }
+
+    fn change_trust(&mut self, amount: i32) {
+        self.trust = (self.trust + amount).clamp(-MAX_AFFINITY, MAX_AFFINITY);
+    }
+
+    fn change_friendliness(&mut self, amount: i32) {
+        self.friendliness = (self.friendliness + amount).clamp(-MAX_AFFINITY, MAX_AFFINITY);
+    }
+}
+
+// The relationships of an npc, keyed by the name of the other character
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Relationships {
+    with: HashMap<String, Relationship>,
+}
+
+impl Relationships {
+    pub fn get(&self, name: &str) -> Relationship {
+        self.with.get(name).cloned().unwrap_or_default()
+    }
+
+    fn entry(&mut self, name: &str) -> &mut Relationship {
+        self.with.entry(name.to_string()).or_default()
+    }
+}
+
+// The relationship as the model sees it in the NpcContext
+#[derive(Serialize)]
+pub struct RelationshipContext {
+    pub with: String,
+    pub trust: i32,
+    pub friendliness: i32,
+    pub balance: i32,
+    pub discount_percent: i32,
+}
+
+impl RelationshipContext {
+    pub fn new(with: &str, relationship: &Relationship) -> RelationshipContext {
+        RelationshipContext {
+            with: with.to_string(),
+            trust: relationship.trust,
+            friendliness: relationship.friendliness,
+            balance: relationship.balance,
+            discount_percent: relationship.discount_percent(),
+        }
+    }
+}
+
+// Items changing hands move the balance, and keeping up your end of a deal builds trust
+fn update_from_actions(
+    mut npc_query: Query<(&Npc, &mut Relationships)>,
+    item_registry: Res<ItemRegistry>,
+    mut on_action_outcome: EventReader<ActionOutcomeEvent>,
+) {
+    for outcome in on_action_outcome.read() {
+        let Action::Give { item, amount } = &outcome.action else {
+            continue;
+        };
+        let value = item_registry.resolve(item).map(|item| item.value(*amount)).unwrap_or(0);
+        for (npc, mut relationships) in npc_query.iter_mut() {
+            if npc.name == outcome.receiver_id {
+                let relationship = relationships.entry(&outcome.sender_id);
+                match outcome.result {
+                    Ok(()) => {
+                        relationship.balance = relationship.balance.saturating_add(value);
+                        relationship.traded = relationship.traded.saturating_add(value);
+                        if relationship.balance >= 0 {
+                            relationship.change_trust(TRADE_TRUST);
+                        }
+                    }
+                    Err(_) => relationship.change_trust(FAILED_GIVE_TRUST),
+                }
+                debug!("{} now thinks of {}: {:?}", npc.name, outcome.sender_id, relationship);
+            } else if npc.name == outcome.sender_id && outcome.result.is_ok() {
+                let relationship = relationships.entry(&outcome.receiver_id);
+                relationship.balance = relationship.balance.saturating_sub(value);
+            }
+        }
+    }
+}
+
+// A completed trade counts like both sides giving their items, the npc's margin ends up in its favor
+fn update_from_trades(mut npc_query: Query<(&Npc, &mut Relationships)>, mut on_trade_completed: EventReader<TradeCompletedEvent>) {
+    for trade in on_trade_completed.read() {
+        for (npc, mut relationships) in npc_query.iter_mut() {
+            let other = if npc.name == trade.proposer {
+                &trade.responder
+            } else if npc.name == trade.responder {
+                &trade.proposer
+            } else {
+                continue;
+            };
+            let (gave, got) = trade.values_for(&npc.name);
+            let relationship = relationships.entry(other);
+            relationship.balance = relationship.balance.saturating_add(got.saturating_sub(gave));
+            relationship.traded = relationship.traded.saturating_add(got);
+            if relationship.balance >= 0 {
+                relationship.change_trust(TRADE_TRUST);
+            }
+            debug!("{} now thinks of {}: {:?}", npc.name, other, relationship);
+        }
+    }
+}
+
+fn update_from_sentiment(mut npc_query: Query<(&Npc, &mut Relationships)>, mut on_sentiment: EventReader<SentimentEvent>) {
+    for event in on_sentiment.read() {
+        let step = match event.sentiment {
+            Sentiment::Negative => -SENTIMENT_STEP,
+            Sentiment::Neutral => 0,
+            Sentiment::Positive => SENTIMENT_STEP,
+        };
+        if step == 0 {
+            continue;
+        }
+        for (npc, mut relationships) in npc_query.iter_mut() {
+            if npc.name == event.npc {
+                relationships.entry(&event.towards).change_friendliness(step);
+            }
+        }
+    }
+}
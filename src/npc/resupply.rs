@@ -0,0 +1,266 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::log::{info, warn};
+use bevy::math::{Vec3, Vec3Swizzles};
+use bevy::prelude::{Commands, Component, Entity, EventReader, Has, IntoSystemConfigs, Query, Res, Time, Timer, TimerMode, Transform, Visibility};
+use bevy_rapier3d::prelude::{ColliderDisabled, RigidBodyDisabled};
+use serde::{Deserialize, Serialize};
+use crate::Action;
+use crate::action_executor::ActionOutcomeEvent;
+use crate::clock::GameClock;
+use crate::communication::{ChatMessage, MessageRole};
+use crate::inventory::Inventory;
+use crate::item_registry::ItemRegistry;
+use crate::navigation::{Locations, NavGrid, NavPath};
+use crate::npc::npc::Npc;
+use crate::npc::npc_definition::InventoryEntry;
+use crate::trade::TradeCompletedEvent;
+
+// Sales from the last three in-game days count as recent demand
+const DEMAND_WINDOW_MINUTES: f64 = 3.0 * 24.0 * 60.0;
+// Every recent sale adds this many items on top of the stock target
+const DEMAND_WEIGHT: f32 = 0.5;
+// Demand never pushes the stock above this many times the target
+const MAX_STOCK_FACTOR: i32 = 2;
+// Below this part of the target the npc is told it's running low
+const LOW_STOCK_FRACTION: f32 = 0.25;
+// How long restocking takes once at the supply location, and how long the trip takes when the npc can't walk there
+const RESTOCK_SECONDS: f32 = 5.0;
+const ABSENCE_SECONDS: f32 = 30.0;
+// Close enough to the supply location to restock
+const ARRIVE_DISTANCE: f32 = 1.5;
+
+pub struct ResupplyPlugin;
+
+impl Plugin for ResupplyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (track_sales, run_resupply_trips).chain());
+    }
+}
+
+// What a merchant keeps in stock, e.g. stock: Some((targets: [(item: "steel_sword", amount: 5)], location: "storehouse"))
+#[derive(Deserialize, Clone, Debug)]
+pub struct StockDefinition {
+    pub targets: Vec<InventoryEntry>,
+    // Named location the npc walks to for new stock
+    #[serde(default = "default_supply_location")]
+    pub location: String,
+}
+
+fn default_supply_location() -> String {
+    "storehouse".to_string()
+}
+
+struct Sale {
+    item: String,
+    amount: i32,
+    // GameClock minutes
+    at: f64,
+}
+
+// Added to merchants. Tracks what they sell so Resupply brings more of what's in demand
+#[derive(Component)]
+pub struct Stock {
+    // By item id
+    pub targets: Vec<InventoryEntry>,
+    pub location: String,
+    sales: Vec<Sale>,
+}
+
+impl Stock {
+    pub fn new(definition: &StockDefinition) -> Stock {
+        Stock { targets: definition.targets.clone(), location: definition.location.clone(), sales: Vec::new() }
+    }
+
+    // Swaps in new targets from an edited definition, the sales so far still count
+    pub fn set_definition(&mut self, definition: &StockDefinition) {
+        self.targets = definition.targets.clone();
+        self.location = definition.location.clone();
+    }
+
+    fn recent_sales(&self, item: &str, now: f64) -> i32 {
+        self.sales.iter().filter(|sale| sale.item == item && now - sale.at <= DEMAND_WINDOW_MINUTES).map(|sale| sale.amount).sum()
+    }
+
+    // How many of an item the npc wants after restocking, more when it sold well lately
+    pub fn desired(&self, target: &InventoryEntry, now: f64) -> i32 {
+        let demand = (self.recent_sales(&target.item, now) as f32 * DEMAND_WEIGHT).round() as i32;
+        (target.amount + demand).min(target.amount * MAX_STOCK_FACTOR)
+    }
+
+    // Items that are nearly sold out
+    pub fn running_low(&self, inventory: &Inventory) -> Vec<String> {
+        self.targets
+            .iter()
+            .filter(|target| (inventory.count(&target.item) as f32) < target.amount as f32 * LOW_STOCK_FRACTION)
+            .map(|target| target.item.clone())
+            .collect()
+    }
+}
+
+enum TripStage {
+    // Walking to the supply location
+    Going,
+    Restocking(Timer),
+    // Walking back to where the trip started
+    Returning,
+    // No way to walk there, the npc is gone for a while instead
+    Away(Timer),
+}
+
+// Added by the Resupply action for the duration of the trip. Npcs on a trip don't trade
+#[derive(Component)]
+pub struct Resupplying {
+    home: Vec3,
+    stage: TripStage,
+}
+
+impl Resupplying {
+    pub fn walking(home: Vec3) -> Resupplying {
+        Resupplying { home, stage: TripStage::Going }
+    }
+
+    pub fn away(home: Vec3) -> Resupplying {
+        Resupplying { home, stage: TripStage::Away(Timer::from_seconds(ABSENCE_SECONDS, TimerMode::Once)) }
+    }
+}
+
+// The stock as the model sees it in the NpcContext
+#[derive(Serialize)]
+pub struct StockContext {
+    pub running_low: Vec<String>,
+    pub supply_location: String,
+    pub resupplying: bool,
+}
+
+impl StockContext {
+    pub fn new(stock: &Stock, inventory: &Inventory, resupplying: bool) -> StockContext {
+        StockContext { running_low: stock.running_low(inventory), supply_location: stock.location.clone(), resupplying }
+    }
+}
+
+// Every item a merchant gives away or trades away counts as a sale of that item
+fn track_sales(
+    clock: Res<GameClock>,
+    item_registry: Res<ItemRegistry>,
+    mut npc_query: Query<(&mut Npc, &Inventory, &mut Stock)>,
+    mut on_action_outcome: EventReader<ActionOutcomeEvent>,
+    mut on_trade_completed: EventReader<TradeCompletedEvent>,
+) {
+    let now = clock.minutes();
+    let mut sold = Vec::new();
+    for outcome in on_action_outcome.read() {
+        let (Action::Give { item, amount }, Ok(())) = (&outcome.action, &outcome.result) else {
+            continue;
+        };
+        if let Ok(item) = item_registry.resolve(item) {
+            sold.push((outcome.sender_id.clone(), item.clone(), *amount));
+        }
+    }
+    for trade in on_trade_completed.read() {
+        sold.extend(trade.proposer_gave.iter().map(|(item, amount)| (trade.proposer.clone(), item.clone(), *amount)));
+        sold.extend(trade.responder_gave.iter().map(|(item, amount)| (trade.responder.clone(), item.clone(), *amount)));
+    }
+    for (seller, item, amount) in sold {
+        for (mut npc, inventory, mut stock) in npc_query.iter_mut() {
+            if npc.name != seller || !stock.targets.iter().any(|target| target.item == item.id) {
+                continue;
+            }
+            stock.sales.retain(|sale| now - sale.at <= DEMAND_WINDOW_MINUTES);
+            stock.sales.push(Sale { item: item.id.clone(), amount, at: now });
+            if stock.running_low(inventory).contains(&item.id) {
+                npc.message_history.push(ChatMessage::new(
+                    MessageRole::System,
+                    format!("Game: You're running low on {}, send Resupply to restock at the {}", item.name, stock.location),
+                ));
+            }
+        }
+    }
+}
+
+// Tops the inventory up to the desired amount of every stocked item, returns what was added
+fn restock(stock: &Stock, inventory: &mut Inventory, item_registry: &ItemRegistry, now: f64) -> Vec<String> {
+    let mut restocked = Vec::new();
+    for target in &stock.targets {
+        let missing = stock.desired(target, now) - inventory.count(&target.item);
+        if missing <= 0 {
+            continue;
+        }
+        let Some(item) = item_registry.get(&target.item) else {
+            continue;
+        };
+        match inventory.add(item, missing) {
+            Ok(()) => restocked.push(format!("{} {}", missing, item.name)),
+            Err(err) => warn!("Could not restock {} {}: {}", missing, item.name, err),
+        }
+    }
+    restocked
+}
+
+// Moves npcs through their trip: walk there, restock, walk back. Npcs that can't walk are hidden until they return
+fn run_resupply_trips(
+    mut commands: Commands,
+    time: Res<Time>,
+    clock: Res<GameClock>,
+    item_registry: Res<ItemRegistry>,
+    locations: Res<Locations>,
+    nav_grid: Option<Res<NavGrid>>,
+    mut npc_query: Query<(Entity, &mut Npc, &Transform, &mut Inventory, &Stock, &mut Resupplying, Has<NavPath>)>,
+) {
+    for (entity, mut npc, transform, mut inventory, stock, mut trip, walking) in npc_query.iter_mut() {
+        let home = trip.home;
+        let restocked = match &mut trip.stage {
+            TripStage::Going => {
+                if !walking {
+                    let arrived = locations
+                        .get(&stock.location)
+                        .is_some_and(|place| place.distance(transform.translation.xz()) <= ARRIVE_DISTANCE);
+                    if arrived {
+                        trip.stage = TripStage::Restocking(Timer::from_seconds(RESTOCK_SECONDS, TimerMode::Once));
+                    } else {
+                        // Stopped walking before getting there, there's nothing to restock from here
+                        warn!("{} didn't make it to the {}, the trip is off", npc.name, stock.location);
+                        commands.entity(entity).remove::<Resupplying>();
+                        npc.message_history.push(ChatMessage::new(
+                            MessageRole::System,
+                            format!("Game: You never made it to the {} and didn't restock", stock.location),
+                        ));
+                    }
+                }
+                continue;
+            }
+            TripStage::Restocking(timer) => {
+                if !timer.tick(time.delta()).finished() {
+                    continue;
+                }
+                let restocked = restock(stock, &mut inventory, &item_registry, clock.minutes());
+                match nav_grid.as_deref().and_then(|grid| NavPath::plan(grid, transform.translation, home.xz())) {
+                    Some(path) => {
+                        commands.entity(entity).insert(path);
+                        trip.stage = TripStage::Returning;
+                    }
+                    None => {
+                        commands.entity(entity).remove::<Resupplying>();
+                    }
+                }
+                restocked
+            }
+            TripStage::Returning => {
+                if !walking {
+                    commands.entity(entity).remove::<Resupplying>();
+                    info!("{} is back from the {}", npc.name, stock.location);
+                }
+                continue;
+            }
+            TripStage::Away(timer) => {
+                if !timer.tick(time.delta()).finished() {
+                    continue;
+                }
+                commands.entity(entity).remove::<(Resupplying, ColliderDisabled, RigidBodyDisabled)>().insert(Visibility::Inherited);
+                restock(stock, &mut inventory, &item_registry, clock.minutes())
+            }
+        };
+        let restocked = if restocked.is_empty() { "nothing, you were fully stocked".to_string() } else { restocked.join(", ") };
+        info!("{} restocked {} at the {}", npc.name, restocked, stock.location);
+        npc.message_history.push(ChatMessage::new(MessageRole::System, format!("Game: You restocked {} at the {}", restocked, stock.location)));
+    }
+}
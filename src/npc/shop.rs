@@ -0,0 +1,168 @@
+use bevy::app::{App, Plugin, Update};
+use bevy::log::info;
+use bevy::prelude::{Component, Query, Res};
+use serde::{Deserialize, Serialize};
+use crate::clock::{GameClock, Weekday};
+use crate::communication::{ChatMessage, MessageRole};
+use crate::npc::npc::Npc;
+
+// Opens and closes shops on their opening hours. Npcs can still open or close early with the Open and Close actions,
+// that lasts until the next time the hours say otherwise
+pub struct ShopPlugin;
+
+impl Plugin for ShopPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, keep_opening_hours);
+    }
+}
+
+// When a shop is open, e.g. shop: (open: 9, close: 18, days: [Monday, Tuesday]) in an npc definition.
+// A close hour before the open hour keeps the shop open past midnight
+#[derive(Deserialize, Clone, Debug)]
+pub struct OpeningHours {
+    pub open: u32,
+    pub close: u32,
+    // Every day when left out
+    #[serde(default = "every_day")]
+    pub days: Vec<Weekday>,
+}
+
+fn every_day() -> Vec<Weekday> {
+    Weekday::ALL.to_vec()
+}
+
+impl OpeningHours {
+    // Checked when the npc definition is loaded
+    pub fn validate(&self) -> Result<(), String> {
+        if self.open >= 24 || self.close >= 24 {
+            return Err(format!("opening hours {}-{} have to be between 0 and 23", self.open, self.close));
+        }
+        if self.open == self.close {
+            return Err(format!("opening hours {}-{} open and close at the same hour", self.open, self.close));
+        }
+        Ok(())
+    }
+
+    pub fn is_open_at(&self, clock: &GameClock) -> bool {
+        let hour = clock.hour();
+        if self.open <= self.close {
+            self.days.contains(&clock.weekday()) && hour >= self.open && hour < self.close
+        } else {
+            // The early hours belong to the day before
+            let yesterday = Weekday::ALL[(clock.day() as usize + 6) % 7];
+            (self.days.contains(&clock.weekday()) && hour >= self.open) || (self.days.contains(&yesterday) && hour < self.close)
+        }
+    }
+}
+
+// Added to npcs that run a business. Closed shops don't trade
+#[derive(Component)]
+pub struct Shop {
+    pub hours: OpeningHours,
+    pub open: bool,
+    // What the hours said last time they were checked, so only a change in schedule overrides the npc
+    scheduled: bool,
+}
+
+impl Shop {
+    pub fn new(hours: OpeningHours, clock: &GameClock) -> Shop {
+        let open = hours.is_open_at(clock);
+        Shop { hours, open, scheduled: open }
+    }
+}
+
+// The shop as the model sees it in the NpcContext
+#[derive(Serialize)]
+pub struct ShopContext {
+    pub open: bool,
+    pub hours: String,
+    pub days: Vec<Weekday>,
+}
+
+impl ShopContext {
+    pub fn new(shop: &Shop) -> ShopContext {
+        ShopContext {
+            open: shop.open,
+            hours: format!("{:02}:00-{:02}:00", shop.hours.open, shop.hours.close),
+            days: shop.hours.days.clone(),
+        }
+    }
+}
+
+// Opens and closes shops when their hours start and end, and lets the npc know so it can mention it
+fn keep_opening_hours(clock: Res<GameClock>, mut shop_query: Query<(&mut Npc, &mut Shop)>) {
+    for (mut npc, mut shop) in shop_query.iter_mut() {
+        let scheduled = shop.hours.is_open_at(&clock);
+        if scheduled == shop.scheduled {
+            continue;
+        }
+        shop.scheduled = scheduled;
+        if shop.open == scheduled {
+            continue;
+        }
+        shop.open = scheduled;
+        let change = if scheduled { "opened" } else { "closed" };
+        info!("{} {} their shop at {}", npc.name, change, *clock);
+        npc.message_history.push(ChatMessage::new(MessageRole::System, format!("Game: It's {}, you {} your shop", *clock, change)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hours(open: u32, close: u32, days: &[Weekday]) -> OpeningHours {
+        OpeningHours { open, close, days: days.to_vec() }
+    }
+
+    // The clock on the given day since the start, at the given hour. Day 0 is a Monday
+    fn at(day: u32, hour: u32) -> GameClock {
+        let mut clock = GameClock::default();
+        clock.set_minutes(((day * 24 + hour) * 60) as f64);
+        clock
+    }
+
+    #[test]
+    fn day_hours_include_open_and_exclude_close() {
+        let shop = hours(9, 18, &Weekday::ALL);
+        assert!(!shop.is_open_at(&at(0, 8)));
+        assert!(shop.is_open_at(&at(0, 9)));
+        assert!(shop.is_open_at(&at(0, 17)));
+        assert!(!shop.is_open_at(&at(0, 18)));
+    }
+
+    #[test]
+    fn overnight_hours_wrap_past_midnight() {
+        let tavern = hours(20, 2, &Weekday::ALL);
+        assert!(!tavern.is_open_at(&at(3, 19)));
+        assert!(tavern.is_open_at(&at(3, 20)));
+        assert!(tavern.is_open_at(&at(3, 23)));
+        assert!(tavern.is_open_at(&at(4, 0)));
+        assert!(tavern.is_open_at(&at(4, 1)));
+        assert!(!tavern.is_open_at(&at(4, 2)));
+        assert!(!tavern.is_open_at(&at(4, 12)));
+    }
+
+    #[test]
+    fn early_hours_belong_to_the_previous_day() {
+        // Only opens friday evening, so it's open early saturday but not early friday
+        let tavern = hours(20, 2, &[Weekday::Friday]);
+        assert!(!tavern.is_open_at(&at(4, 1)));
+        assert!(tavern.is_open_at(&at(4, 21)));
+        assert!(tavern.is_open_at(&at(5, 1)));
+        assert!(!tavern.is_open_at(&at(5, 21)));
+        // Sunday night into monday wraps around the week
+        let sunday = hours(22, 3, &[Weekday::Sunday]);
+        assert!(sunday.is_open_at(&at(7, 2)));
+        assert!(!sunday.is_open_at(&at(8, 2)));
+    }
+
+    #[test]
+    fn rejects_impossible_hours() {
+        assert!(hours(9, 18, &[]).validate().is_ok());
+        assert!(hours(22, 0, &[]).validate().is_ok());
+        assert!(hours(9, 24, &[]).validate().is_err());
+        assert!(hours(25, 3, &[]).validate().is_err());
+        assert!(hours(9, 9, &[]).validate().is_err());
+    }
+}
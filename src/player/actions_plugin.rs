@@ -1,7 +1,5 @@
-use std::cmp::max;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::io::Cursor;
-use std::mem;
 use std::sync::Arc;
 use bevy::{
     app::{Plugin, PostUpdate, Update},
@@ -10,34 +8,40 @@ use bevy::{
     tasks::{IoTaskPool, block_on, Task, futures_lite::{future}},
 };
 use bevy::app::Startup;
+use bevy::ecs::system::SystemParam;
 use bevy::asset::AssetContainer;
 use bevy::ecs::bundle::DynamicBundle;
-use bevy::input::keyboard::{Key, KeyboardInput};
 use bevy::log::{info};
-use bevy::text::Text2dBounds;
-use bevy_mod_billboard::{BillboardDepth, BillboardTextBundle};
-use bevy_mod_billboard::prelude::BillboardTextBounds;
 use bevy_tokio_tasks::TokioTasksRuntime;
 use nalgebra::DimAdd;
 use serde_json::Error;
-use serde_json_any_key::MapIterToJson;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
 use tokio::task::JoinHandle;
-use crate::character::CharacterTrait;
-use crate::communication::{ChatMessage, ChatResponse, Communicator, MessageRole};
-use crate::Interaction;
+use crate::action_executor::ExecuteActionsEvent;
+use crate::inventory::Inventory;
+use crate::communication::{extract_partial_message, ChatMessage, MessageRole};
+use crate::interaction_parser::{request_interaction, ParseFailure};
+use crate::{Action, Interaction, NpcContext};
+use crate::clock::{GameClock, TimeContext};
 use crate::npc::npc::Npc;
+use crate::npc::resupply::{Resupplying, Stock, StockContext};
+use crate::npc::shop::{Shop, ShopContext};
+use crate::npc::relationship::{Relationships, RelationshipContext, SentimentEvent};
+use crate::player::chat_input_plugin::{edit_chat_input, ChatClosedEvent, ChatInput, ChatSubmittedEvent};
 use crate::player::player::Player;
+use crate::player::speech_bubble_plugin::{create_text_bundle, Bubble, SpeakEvent};
+use crate::quest::{QuestContext, QuestLog, QuestRegistry};
+use crate::guard::{self, BlockReason, Guard, ReplyGuard, ReplyPolicy};
+use crate::trade::parse_trade_command;
+use crate::tts::{SpeechRequest, Tts, TtsError, VoiceProfile};
 
 pub struct ActionsPlugin;
 
-// Scales all the 'text-bubbles' spawned above the characters
-const TEXT_SCALE: Vec3 = Vec3::splat(0.0030);
+// How much of a reply that is still streaming in is shown in its bubble
+const STREAMING_BUBBLE_CHARS: usize = 200;
 
-// Keep track of remaining text that has yet to be displayed above characters
-#[derive(Resource)]
-struct ChatBubble {
-    queue: HashMap<String, VecDeque<String>>,
-}
+// How close the player has to be to an npc to start talking to it
+const TALK_DISTANCE: f32 = 2.0;
 
 // To let systems know if the player is currently typing
 // Current listeners:
@@ -53,18 +57,113 @@ pub struct ToggleInputEvent {
 #[derive(Event)]
 pub struct AiRequestEvent {
     pub msg: String,
+    // The npc the message is addressed to
+    pub npc: Entity,
+    // Actions the player took along with the message, e.g. an Offer typed as /offer
+    pub actions: Vec<Action>,
+}
+
+// The npc the player opened the chat with, only this npc receives the player's messages.
+// Cleared when the chat is closed or the player walks out of talking distance
+#[derive(Resource, Default)]
+pub struct ActiveConversation {
+    pub npc: Option<Entity>,
+}
+
+impl ActiveConversation {
+    pub fn is_with(&self, npc: Entity) -> bool {
+        self.npc == Some(npc)
+    }
+}
+
+// What an npc saying something sets off, whoever it talks to
+#[derive(SystemParam)]
+pub struct DialogueEvents<'w> {
+    pub on_speak: EventWriter<'w, SpeakEvent>,
+    pub on_sentiment: EventWriter<'w, SentimentEvent>,
+    pub on_execute_actions: EventWriter<'w, ExecuteActionsEvent>,
 }
 
 // Keeps track of the AI responses tied to the character ID that requested a response
 #[derive(Resource)]
 struct AiRequestTask {
-    generated_response: HashMap<String, JoinHandle<Interaction>>,
+    generated_response: HashMap<String, PendingReply>,
+}
+
+struct PendingReply {
+    player_name: String,
+    npc_name: String,
+    // What the player said, taken back out of the npc's history when the classifier blocks it
+    message: String,
+    // What the npc could plausibly do in its reply, checked once it's there
+    policy: ReplyPolicy,
+    task: JoinHandle<Result<Interaction, ReplyError>>,
+}
+
+enum ReplyError {
+    Failed(AiResponseFailedEvent),
+    // The classifier flagged the player's message, the npc never answered it
+    Blocked(BlockReason),
+}
+
+// Emitted when the model didn't come up with a usable response, even after being asked to repair it
+#[derive(Event)]
+pub struct AiResponseFailedEvent {
+    pub player_name: String,
+    pub npc_name: String,
+    pub failure: ParseFailure,
+}
+
+// Keeps track of the replies that are still being streamed in, keyed the same way as AiRequestTask
+#[derive(Resource)]
+struct AiResponseStream {
+    streams: HashMap<String, ResponseStream>,
+}
+
+struct ResponseStream {
+    npc_name: String,
+    // Everything the model generated so far, a json object that is not finished yet
+    raw: String,
+    receiver: UnboundedReceiver<String>,
+}
+
+// Marks the bubble that shows a reply while it is still being generated
+#[derive(Component)]
+struct StreamingBubble {
+    key: String,
+}
+
+// What a request to an npc is built from and screened with
+#[derive(SystemParam)]
+struct RequestSources<'w> {
+    quest_registry: Res<'w, QuestRegistry>,
+    clock: Res<'w, GameClock>,
+    guard: Res<'w, Guard>,
+}
+
+// The replies still being generated along with their streams and streaming bubbles
+#[derive(SystemParam)]
+struct PendingReplies<'w, 's> {
+    commands: Commands<'w, 's>,
+    tasks: ResMut<'w, AiRequestTask>,
+    streams: ResMut<'w, AiResponseStream>,
+    streaming_bubbles: Query<'w, 's, (Entity, &'static StreamingBubble)>,
+}
+
+// The chat input and the conversation it's typed into
+#[derive(SystemParam)]
+struct ChatSession<'w, 's> {
+    input_query: Query<'w, 's, &'static mut ChatInput>,
+    on_submit: EventReader<'w, 's, ChatSubmittedEvent>,
+    on_close: EventReader<'w, 's, ChatClosedEvent>,
+    toggle_input_events: EventWriter<'w, ToggleInputEvent>,
+    conversation: ResMut<'w, ActiveConversation>,
 }
 
 // Keeps track of TTS requests
 #[derive(Resource)]
 struct TTSRequest {
-    generated_tts: HashMap<String, JoinHandle<Vec<u8>>>,
+    generated_tts: HashMap<String, (Entity, JoinHandle<Result<Vec<u8>, TtsError>>)>,
 }
 
 // When a tts request is requested
@@ -72,25 +171,16 @@ struct TTSRequest {
 struct TTSRequestEvent {
     msg: String,
     id: String,
-}
-
-// Component added to TextBundles so we can make them disappear after a while
-#[derive(Component, Eq, PartialEq)]
-struct Bubble {
-    id: String,
-    timer: Timer,
-}
-
-// Allows for the listen_keyboard_input_events function to have a local state
-#[derive(Default)]
-struct SystemInputState {
-    is_typing: bool,
+    // The character that speaks, the audio plays from its position
+    speaker: Entity,
+    voice: VoiceProfile,
 }
 
 // Initialize our resources
 fn create_resource(mut commands: Commands) {
     commands.insert_resource(AiRequestTask { generated_response: HashMap::new() });
-    commands.insert_resource(ChatBubble { queue: HashMap::new() });
+    commands.insert_resource(AiResponseStream { streams: HashMap::new() });
+    commands.insert_resource(ActiveConversation::default());
     commands.insert_resource(TTSRequest { generated_tts: HashMap::new() });
 }
 
@@ -99,19 +189,22 @@ impl Plugin for ActionsPlugin {
         app.add_event::<ToggleInputEvent>();
         app.add_event::<AiRequestEvent>();
         app.add_event::<TTSRequestEvent>();
-        app.add_systems(Startup, (create_resource, setup_scene));
-        app.add_systems(Update, make_bubbles_follow_entities);
-        app.add_systems(Update, (request_tts, play_tts));
-        app.add_systems(PostUpdate, (make_ai_request, listen_keyboard_input_events).run_if(resource_exists::<AiRequestTask>));
-        app.add_systems(PostUpdate, (get_ai_response.run_if(resource_exists::<AiRequestTask>), bubbling_text));
+        app.add_event::<AiResponseFailedEvent>();
+        app.add_systems(Startup, create_resource);
+        app.add_systems(Update, (request_tts, play_tts, leave_conversation));
+        app.add_systems(PostUpdate, (make_ai_request, listen_keyboard_input_events.after(edit_chat_input)).run_if(resource_exists::<AiRequestTask>));
+        app.add_systems(PostUpdate, get_ai_response.run_if(resource_exists::<AiRequestTask>));
+        app.add_systems(Update, stream_ai_response.run_if(resource_exists::<AiResponseStream>));
     }
 }
 
-// Listen for AI requests and create async runtime functions to wait for responses
+// Listen for AI requests and create async runtime functions to wait for responses of the addressed npc
 fn make_ai_request(
-    mut player_query: Query<&mut Player>,
-    mut npc_query: Query<&mut Npc>,
+    player_query: Query<(&Player, &QuestLog)>,
+    mut npc_query: Query<(&mut Npc, &Inventory, &Relationships, Option<&Shop>, Option<&Stock>, Has<Resupplying>)>,
+    sources: RequestSources,
     mut my_tasks: ResMut<AiRequestTask>,
+    mut streams: ResMut<AiResponseStream>,
     runtime: ResMut<TokioTasksRuntime>,
     mut on_ai_request: EventReader<AiRequestEvent>,
 ) {
@@ -120,38 +213,65 @@ fn make_ai_request(
     }
 
     for req in on_ai_request.read() {
-        for mut player in player_query.iter_mut() {
-            for mut npc in npc_query.iter_mut() {
+        for (player, quest_log) in player_query.iter() {
+            if let Ok((mut npc, inventory, relationships, shop, stock, resupplying)) = npc_query.get_mut(req.npc) {
                 let message = req.msg.clone();
+                let actions = req.actions.clone();
                 npc.message_history.push(ChatMessage::new(MessageRole::User, message.clone()));
                 let mut npc_clone = npc.clone();
+                let quests = QuestContext::new(&sources.quest_registry, &npc.name, quest_log);
+                let mut policy = ReplyPolicy::new(&sources.guard, &npc, &player.name, &message, (shop.is_some(), stock.is_some()), &quests);
+                // Trade commands are checked by the executor, only free text can talk the npc into something
+                if !req.actions.is_empty() {
+                    policy.suspicion = None;
+                }
+                let context = NpcContext {
+                    npc_inventory: inventory.items().clone(),
+                    relationship: RelationshipContext::new(&player.name, &relationships.get(&player.name)),
+                    quests,
+                    time: TimeContext::new(&sources.clock),
+                    shop: shop.map(ShopContext::new),
+                    stock: stock.map(|stock| StockContext::new(stock, inventory, resupplying)),
+                };
                 let player_clone = player.clone();
                 let p_name = player.name.clone();
                 let n_name = npc.name.clone();
+                let (tokens, receiver) = unbounded_channel();
+                let classify = sources.guard.config.classifier;
+                let pending_message = message.clone();
 
-                let task = runtime.spawn_background_task(|mut ctx| async move {
+                let task = runtime.spawn_background_task(move |mut ctx| async move {
+                    if classify {
+                        guard::classify(npc_clone.llm.as_ref(), &message).await.map_err(ReplyError::Blocked)?;
+                    }
                     let p_name = player_clone.name.clone();
                     let n_name = npc_clone.name.clone();
                     let it = Interaction {
                         sender_id: p_name,
                         receiver_id: n_name,
                         message,
-                        actions: vec![],
+                        actions,
+                        sentiment: Default::default(),
                     };
 
+
                     let mut js = serde_json::to_string(&it).unwrap();
-                    js.push_str(npc_clone.get_items().to_json_map().unwrap().as_str());
+                    js.push_str(serde_json::to_string(&context).unwrap().as_str());
 
                     let cm = ChatMessage::new(MessageRole::User, js);
-                    let t = npc_clone.talk(cm).await;
-                    serde_json::from_str::<Interaction>(t.as_str()).unwrap_or(Interaction {
-                        sender_id: "error".to_string(),
-                        receiver_id: "error".to_string(),
-                        message: format!("There has been an error converting the AI response (actions_plugin::make_ai_request). Response: {}", t),
-                        actions: vec![],
+                    request_interaction(&mut npc_clone, cm, tokens).await.map_err(|failure| {
+                        ReplyError::Failed(AiResponseFailedEvent { player_name: it.sender_id, npc_name: it.receiver_id, failure })
                     })
                 });
-                my_tasks.generated_response.insert(format!("{}-{}", p_name, n_name), task);
+                let key = format!("{}-{}", p_name, n_name);
+                streams.streams.insert(key.clone(), ResponseStream { npc_name: n_name.clone(), raw: String::new(), receiver });
+                my_tasks.generated_response.insert(key, PendingReply {
+                    player_name: p_name,
+                    npc_name: n_name,
+                    message: pending_message,
+                    policy,
+                    task,
+                });
             }
         }
     }
@@ -159,60 +279,69 @@ fn make_ai_request(
 
 // Checks if the AI responses are done and if so; handles them
 fn get_ai_response(
-    mut commands: Commands,
     mut player_query: Query<&mut Player>,
-    mut npc_query: Query<(&mut Npc, &Transform)>,
-    mut my_tasks: ResMut<AiRequestTask>,
-    mut bubble_queue: ResMut<ChatBubble>,
-    mut on_tts_request: EventWriter<TTSRequestEvent>
+    mut npc_query: Query<(Entity, &mut Npc)>,
+    mut replies: PendingReplies,
+    mut dialogue: DialogueEvents,
+    mut on_tts_request: EventWriter<TTSRequestEvent>,
+    mut on_ai_response_failed: EventWriter<AiResponseFailedEvent>,
+    mut reply_guard: ReplyGuard,
 ) {
-    my_tasks.generated_response.retain(|id, task| {
-        let status = block_on(future::poll_once(task));
+    let PendingReplies { commands, tasks, streams, streaming_bubbles } = &mut replies;
+    tasks.generated_response.retain(|id, pending| {
+        let status = block_on(future::poll_once(&mut pending.task));
 
         let retain = status.is_none();
 
-        if let Some(mut res) = status {
-            if let Ok(content) = res {
-                info!("{}", content.message);
-                for player in player_query.iter_mut() {
-                    for (mut npc, npc_transform) in npc_query.iter_mut() {
-                        if npc.name == content.sender_id {
-                            npc.message_history.push(ChatMessage::new(MessageRole::Assistant, content.message.clone()));
-                            // Determine how many text sections we need depending on the message length;
-                            let sections = max(1, (content.message.len()) / 200);
-                            println!("{}", sections);
-                            let section_size = content.message.len() / sections;
-                            for i in 1..sections {
-                                let mut section = String::new();
-                                if i == sections - 1 {
-                                    section = content.message[i * section_size..].to_string();
-                                } else {
-                                    section = format!("{}{}", content.message[i * section_size..(i + 1) * section_size].to_string(), "...");
-                                }
-                                println!("{}", section);
-                                bubble_queue.queue.entry(npc.name.clone()).or_insert(VecDeque::from(vec![section.to_string()])).push_back(section.to_string());
-                            }
-                            // Spawn text
-                            commands.spawn(
-                                (BillboardTextBundle {
-                                    transform: Transform::from_xyz(npc_transform.translation.x, npc_transform.translation.y + 1.5, npc_transform.translation.z).with_scale(TEXT_SCALE),
-                                    text: Text::from_section(
-                                        &content.message[..section_size].to_string(),
-                                        TextStyle {
-                                            font_size: 60.0,
-                                            color: Color::WHITE,
-                                            ..default()
-                                        },
-                                    )
-                                        .with_justify(JustifyText::Center),
-                                    text_bounds: BillboardTextBounds(Text2dBounds { size: Vec2::new(1200., 500.) }),
-                                    billboard_depth: BillboardDepth(false),
-                                    ..default()
-                                }, Bubble { id: npc.name.clone(), timer: Timer::from_seconds(7., TimerMode::Once) }));
-                            // Send text to TTS python server to get audio
-                            on_tts_request.send(TTSRequestEvent{id: npc.name.clone(), msg: content.message.clone()});
-
-                        }
+        if status.is_some() {
+            // The stream is complete, the final bubbles below replace the streaming one
+            streams.streams.remove(id);
+            for (entity, streaming_bubble) in streaming_bubbles.iter() {
+                if &streaming_bubble.key == id {
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+
+        if let Some(Ok(Err(ReplyError::Blocked(reason)))) = status {
+            // Nothing was answered, so the message doesn't stay in the npc's history either
+            if let Some((_, mut npc)) = npc_query.iter_mut().find(|(_, npc)| npc.name == pending.npc_name) {
+                let said = npc.message_history.iter().rposition(|message| {
+                    matches!(message.get_role(), MessageRole::User) && message.get_content() == pending.message
+                });
+                if let Some(index) = said {
+                    npc.message_history.remove(index);
+                    npc.history_generation = npc.history_generation.wrapping_add(1);
+                }
+            }
+            dialogue.on_speak.send(SpeakEvent { id: pending.npc_name.clone(), text: "...".to_string() });
+            reply_guard.block(&pending.player_name, &pending.npc_name, reason);
+        } else if let Some(Ok(Err(ReplyError::Failed(failed)))) = status {
+            warn!("{} could not respond to {}: {}", failed.npc_name, failed.player_name, failed.failure);
+            if let Some(response) = &failed.failure.response {
+                warn!("Last response: {}", response);
+            }
+            // Let the player know the npc heard them even though there is no answer
+            dialogue.on_speak.send(SpeakEvent { id: failed.npc_name.clone(), text: "...".to_string() });
+            on_ai_response_failed.send(failed);
+        } else if let Some(Ok(Ok(mut content))) = status {
+            info!("{}", content.message);
+            reply_guard.check_reply(&mut content, &pending.policy);
+            for player in player_query.iter_mut() {
+                for (npc_entity, mut npc) in npc_query.iter_mut() {
+                    if npc.name == content.sender_id {
+                        npc.message_history.push(ChatMessage::new(MessageRole::Assistant, content.message.clone()));
+                        dialogue.on_sentiment.send(SentimentEvent { npc: npc.name.clone(), towards: content.receiver_id.clone(), sentiment: content.sentiment });
+                        dialogue.on_speak.send(SpeakEvent { id: npc.name.clone(), text: content.message.clone() });
+                        // Send text to TTS python server to get audio
+                        on_tts_request.send(TTSRequestEvent{id: npc.name.clone(), msg: content.message.clone(), speaker: npc_entity, voice: npc.voice.clone()});
+                        // Carry out whatever the npc decided to do
+                        dialogue.on_execute_actions.send(ExecuteActionsEvent {
+                            sender_id: content.sender_id.clone(),
+                            receiver_id: content.receiver_id.clone(),
+                            actions: content.actions.clone(),
+                        });
+
                     }
                 }
             }
@@ -221,209 +350,195 @@ fn get_ai_response(
     });
 }
 
-// Bottom left input text so the player sees what they type
-fn setup_scene(mut commands: Commands, asset_server: Res<AssetServer>) {
-    // The default font has a limited number of glyphs, so use the full version for
-    // sections that will hold text input.
-    let font = asset_server.load("fonts/FiraMono-Medium.ttf");
-
-    commands.spawn(TextBundle {
-        text: Text::from_section(
-            "".to_string(),
-            TextStyle {
-                font,
-                font_size: 20.0,
-                ..default()
-            },
-        ),
-        ..default()
-    }.with_text_justify(JustifyText::Center)
-        // Set the style of the TextBundle itself.
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            bottom: Val::Px(5.0),
-            left: Val::Px(5.0),
-            ..default()
-        }));
-}
-
-// Checks for text bubbles that should be de-spawned
-// Checks if the next section of text should be pushed if the dialogue is big
-fn bubbling_text(
+// Shows replies in a bubble above the npc while they are still being generated
+fn stream_ai_response(
     mut commands: Commands,
-    mut bubbles: Query<(Entity, &mut Transform, &mut Bubble)>,
-    time: Res<Time>,
-    mut bubble_queue: ResMut<ChatBubble>,
+    mut streams: ResMut<AiResponseStream>,
+    npc_query: Query<(&Npc, &Transform)>,
+    mut bubble_query: Query<(&mut Text, &mut Bubble, &StreamingBubble)>,
 ) {
-    for (entity, mut transform, mut bubble) in bubbles.iter_mut() {
-        if bubble.timer.tick(time.delta()).just_finished() {
-            commands.entity(entity).despawn();
-            if bubble_queue.queue.contains_key(&bubble.id) {
-                if bubble_queue.queue[&bubble.id].is_empty() {
-                    return;
-                }
-                commands.spawn(
-                    (BillboardTextBundle {
-                        transform: transform.clone(),
-                        text: Text::from_section(
-                            bubble_queue.queue.get_mut(&bubble.id).unwrap().pop_front().unwrap(),
-                            TextStyle {
-                                font_size: 60.0,
-                                color: Color::WHITE,
-                                ..default()
-                            },
-                        )
-                            .with_justify(JustifyText::Center),
-                        billboard_depth: BillboardDepth(false),
-                        text_bounds: BillboardTextBounds(Text2dBounds { size: Vec2::new(1000., 500.) }),
-                        ..default()
-                    }, Bubble { id: bubble.id.to_string(), timer: Timer::from_seconds(7., TimerMode::Once) }));
-            }
+    for (key, stream) in streams.streams.iter_mut() {
+        let mut received = false;
+        while let Ok(token) = stream.receiver.try_recv() {
+            stream.raw.push_str(&token);
+            received = true;
         }
-    }
-}
-
-// Makes sure the text-bubbles stick to the characters when they move
-fn make_bubbles_follow_entities(player_query: Query<(&Player, &Transform)>, npc_query: Query<(&Npc, &Transform)>, mut bubble_query: Query<(&mut Transform, &mut Bubble), (Without<Player>, Without<Npc>)>) {
-    for (mut bubble_transform, bubble) in bubble_query.iter_mut() {
-        let mut transform = bubble_transform.clone();
-        if let Some((p, p_t)) = player_query.iter().find(|(p, p_t)| p.name == bubble.id) {
-            transform = p_t.clone();
-        } else if let Some((n, n_t)) = npc_query.iter().find(|(n, n_t)| n.name == bubble.id) {
-            transform = n_t.clone();
-        } else {
+        if !received {
+            continue;
+        }
+        let Some(message) = extract_partial_message(&stream.raw) else {
             continue;
+        };
+        if message.is_empty() {
+            continue;
+        }
+        // Only the end of the message fits in the bubble, the full text is paginated once it's done
+        let visible = message.chars().rev().take(STREAMING_BUBBLE_CHARS).collect::<Vec<char>>().into_iter().rev().collect::<String>();
+
+        if let Some((mut text, mut bubble, _)) = bubble_query.iter_mut().find(|(_, _, streaming)| &streaming.key == key) {
+            text.sections[0].value = visible;
+            // Keep the bubble alive for as long as the model is still talking
+            bubble.timer.reset();
+        } else if let Some((_, npc_transform)) = npc_query.iter().find(|(npc, _)| npc.name == stream.npc_name) {
+            let transform = Transform::from_xyz(npc_transform.translation.x, npc_transform.translation.y + 1.5, npc_transform.translation.z);
+            commands.spawn((create_text_bundle(stream.npc_name.clone(), visible, &transform), StreamingBubble { key: key.clone() }));
         }
-        bubble_transform.translation.x = transform.translation.x;
-        bubble_transform.translation.y = transform.translation.y + 1.5;
-        bubble_transform.translation.z = transform.translation.z;
     }
 }
 
+// Space opens the chat with the closest npc in range, the ChatInput takes the keyboard from there
 fn listen_keyboard_input_events(
-    mut commands: Commands,
-    mut events: EventReader<KeyboardInput>,
-    mut edit_text: Query<&mut Text, (Without<Bubble>)>,
-    mut is_typing: Local<SystemInputState>,
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut chat: ChatSession,
     mut emit_ai_request: EventWriter<AiRequestEvent>,
-    mut toggle_input_events: EventWriter<ToggleInputEvent>,
-    mut player_query: Query<(&mut Player, &Transform)>,
-    mut npc_query: Query<(&mut Npc, &Transform)>,
+    mut dialogue: DialogueEvents,
+    player_query: Query<(&Player, &Transform)>,
+    npc_query: Query<(Entity, &Npc, &Transform, &Visibility)>,
+    mut reply_guard: ReplyGuard,
 ) {
-    for event in events.read() {
-        // Only trigger changes when the key is first pressed.
-        if !event.state.is_pressed() {
+    let ChatSession { input_query, on_submit, on_close, toggle_input_events, conversation } = &mut chat;
+    let Ok(mut input) = input_query.get_single_mut() else {
+        return;
+    };
+    for submitted in on_submit.read() {
+        toggle_input_events.send(ToggleInputEvent { is_toggled: false });
+        let Some(npc) = conversation.npc else {
             continue;
-        }
-        match &event.logical_key {
-            Key::Enter => {
-                if !is_typing.is_typing {
-                    continue;
-                }
-                let mut text = edit_text.single_mut();
-                if text.sections[0].value.is_empty() {
-                    continue;
-                }
-                let old_value = mem::take(&mut text.sections[0].value);
-                println!("{}", old_value.clone());
-                let (player, player_transform) = player_query.single_mut();
-                commands.spawn(create_text_bundle(player.name.clone(), old_value.clone(), &Transform::from_xyz(player_transform.translation.x, player_transform.translation.y + 1.5, player_transform.translation.z)));
-                emit_ai_request.send(AiRequestEvent { msg: old_value.clone() });
-                toggle_input_events.send(ToggleInputEvent { is_toggled: false });
-                is_typing.is_typing = false;
-            }
-            Key::Space => {
-                if is_typing.is_typing {
-                    edit_text.single_mut().sections[0].value.push(' ');
-                } else {
-                    for (_, p_transform) in player_query.iter() {
-                        for (__, n_transform) in npc_query.iter() {
-                            if (p_transform.translation - n_transform.translation).length() < 2.0 {
-                                is_typing.is_typing = true;
-                                toggle_input_events.send(ToggleInputEvent { is_toggled: true });
-                            }
-                        }
-                    }
-                }
-            }
-            Key::Backspace => {
-                if !is_typing.is_typing {
+        };
+        let (player, _) = player_query.single();
+        // Trade commands are carried out right away, the npc answers the action instead of the command
+        let (msg, actions) = match parse_trade_command(&submitted.msg) {
+            None => {
+                if let Err(reason) = reply_guard.guard.check_input(&submitted.msg) {
+                    let Ok((_, receiver, ..)) = npc_query.get(npc) else {
+                        continue;
+                    };
+                    dialogue.on_speak.send(SpeakEvent { id: player.name.clone(), text: submitted.msg.clone() });
+                    dialogue.on_speak.send(SpeakEvent { id: receiver.name.clone(), text: "...".to_string() });
+                    reply_guard.block(&player.name, &receiver.name, reason);
                     continue;
                 }
-                edit_text.single_mut().sections[0].value.pop();
+                (submitted.msg.clone(), Vec::new())
             }
-            Key::Escape => {
-                if !is_typing.is_typing {
-                    continue;
-                }
-                edit_text.single_mut().sections[0].value.clear();
-                is_typing.is_typing = false;
-                toggle_input_events.send(ToggleInputEvent { is_toggled: false });
+            Some(Err(usage)) => {
+                warn!("{}", usage);
+                continue;
             }
-            Key::Character(character) => {
-                if !is_typing.is_typing {
+            Some(Ok(action)) => {
+                let Ok((_, receiver, ..)) = npc_query.get(npc) else {
                     continue;
-                }
-                edit_text.single_mut().sections[0].value.push_str(character);
+                };
+                dialogue.on_execute_actions.send(ExecuteActionsEvent {
+                    sender_id: player.name.clone(),
+                    receiver_id: receiver.name.clone(),
+                    actions: vec![action.clone()],
+                });
+                (format!("I {}", action), vec![action])
             }
-            _ => continue,
+        };
+        dialogue.on_speak.send(SpeakEvent { id: player.name.clone(), text: msg.clone() });
+        emit_ai_request.send(AiRequestEvent { msg, npc, actions });
+    }
+    for _ in on_close.read() {
+        conversation.npc = None;
+        toggle_input_events.send(ToggleInputEvent { is_toggled: false });
+    }
+    if input.is_focused() || !key_input.just_pressed(KeyCode::Space) {
+        return;
+    }
+    for (_, p_transform) in player_query.iter() {
+        // Talk to the closest npc in range
+        // Npcs that are away resupplying are hidden and can't be talked to
+        let closest = npc_query
+            .iter()
+            .filter(|(.., visibility)| **visibility != Visibility::Hidden)
+            .map(|(npc, _, n_transform, _)| (npc, (p_transform.translation - n_transform.translation).length()))
+            .filter(|(_, distance)| *distance < TALK_DISTANCE)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((npc, _)) = closest {
+            conversation.npc = Some(npc);
+            input.focus();
+            toggle_input_events.send(ToggleInputEvent { is_toggled: true });
         }
     }
 }
 
-fn create_text_bundle(id: String, msg: String, transform: &Transform) -> (BillboardTextBundle, Bubble) {
-    (BillboardTextBundle {
-        transform: transform.clone().with_scale(TEXT_SCALE),
-        text: Text::from_section(
-            msg,
-            TextStyle {
-                font_size: 60.0,
-                color: Color::WHITE,
-                ..default()
-            },
-        )
-            .with_justify(JustifyText::Center),
-        billboard_depth: BillboardDepth(false),
-        text_bounds: BillboardTextBounds(Text2dBounds { size: Vec2::new(1000., 500.) }),
-        ..default()
-    }, Bubble { id, timer: Timer::from_seconds(7., TimerMode::Once) })
+// Walking away from the npc ends the conversation, unless the player is still typing to it
+fn leave_conversation(
+    mut conversation: ResMut<ActiveConversation>,
+    input_query: Query<&ChatInput>,
+    player_query: Query<&Transform, With<Player>>,
+    npc_query: Query<&Transform, With<Npc>>,
+) {
+    let Some(npc) = conversation.npc else {
+        return;
+    };
+    if input_query.get_single().is_ok_and(|input| input.is_focused()) {
+        return;
+    }
+    let (Ok(p_transform), Ok(n_transform)) = (player_query.get_single(), npc_query.get(npc)) else {
+        conversation.npc = None;
+        return;
+    };
+    if p_transform.translation.distance(n_transform.translation) > TALK_DISTANCE {
+        conversation.npc = None;
+    }
 }
 
 fn request_tts(
     runtime: ResMut<TokioTasksRuntime>,
+    tts: Res<Tts>,
     mut tts_tasks: ResMut<TTSRequest>,
     mut on_tts_request: EventReader<TTSRequestEvent>,
 ) {
     for req in on_tts_request.read() {
-        let msg = req.msg.clone();
-        let task = runtime.spawn_background_task(|mut ctx| async move {
-            text_to_speech(msg.clone()).await.unwrap()
-
+        let request = SpeechRequest { text: req.msg.clone(), voice: req.voice.clone() };
+        let backend = tts.backend.clone();
+        let task = runtime.spawn_background_task(|_ctx| async move {
+            backend.synthesize(&request).await
         });
-        tts_tasks.generated_tts.insert(req.id.clone(), task);
+        tts_tasks.generated_tts.insert(req.id.clone(), (req.speaker, task));
     }
 }
 
 fn play_tts(mut commands: Commands, mut tts_tasks: ResMut<TTSRequest>, mut audio_assets: ResMut<Assets<AudioSource>>) {
-    tts_tasks.generated_tts.retain(|id, task| {
+    tts_tasks.generated_tts.retain(|id, (speaker, task)| {
         let status = block_on(future::poll_once(task));
         let retain = status.is_none();
         if let Some(res) = status {
-            if let Ok(audio) = res {
+            // The npc's text is already on screen, without a tts server it just stays silent
+            let audio = match res {
+                Ok(Ok(audio)) => audio,
+                Ok(Err(err)) => {
+                    warn!("Could not generate speech for {}: {}", id, err);
+                    Vec::new()
+                }
+                Err(err) => {
+                    warn!("Speech task for {} failed: {}", id, err);
+                    Vec::new()
+                }
+            };
+            if !audio.is_empty() {
                 let audio_source = AudioSource { bytes: Arc::from(audio.into_boxed_slice()) };
                 let handle = audio_assets.add(audio_source);
-                commands.spawn(AudioBundle{source: handle, settings: Default::default() });
+                // Played from the speaker so volume and panning follow it around, see player_plugin::create_listener
+                let voice = (
+                    AudioBundle { source: handle, settings: PlaybackSettings::DESPAWN.with_spatial(true) },
+                    SpatialBundle::default(),
+                );
+                match commands.get_entity(*speaker) {
+                    Some(mut speaker) => {
+                        speaker.with_children(|parent| {
+                            parent.spawn(voice);
+                        });
+                    }
+                    // The speaker is gone, still say the line
+                    None => {
+                        commands.spawn(AudioBundle { source: voice.0.source, settings: PlaybackSettings::DESPAWN });
+                    }
+                }
             }
         }
         retain
     });
 }
-
-async fn text_to_speech(text: String) -> Result<Vec<u8>, reqwest::Error> {
-    let uri = format!("http://localhost:4003/generate?text={}", text.replace(" ", "%20"));
-    let http_client = reqwest::Client::new();
-    Ok(http_client
-        .get(uri)
-        .send()
-        .await?.bytes().await?.to_vec())
-}
\ No newline at end of file
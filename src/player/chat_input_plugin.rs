@@ -0,0 +1,246 @@
+use bevy::{
+    app::{Plugin, PostUpdate, Startup},
+    input::{ButtonInput, keyboard::{Key, KeyboardInput}},
+    prelude::*,
+};
+use crate::guard::Guard;
+
+pub struct ChatInputPlugin;
+
+// How many sent messages can be brought back with the arrow keys
+const MAX_HISTORY: usize = 50;
+
+const PROMPT: &str = "> ";
+const CARET: &str = "|";
+
+impl Plugin for ChatInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ChatSubmittedEvent>();
+        app.add_event::<ChatClosedEvent>();
+        app.add_systems(Startup, spawn_chat_input);
+        app.add_systems(PostUpdate, (edit_chat_input, render_chat_input).chain());
+    }
+}
+
+// Emitted when the player presses enter on a non-empty message. The input loses focus
+#[derive(Event)]
+pub struct ChatSubmittedEvent {
+    pub msg: String,
+}
+
+// Emitted when the player closes the input with escape
+#[derive(Event)]
+pub struct ChatClosedEvent;
+
+// The text field the player types messages in. Only the entity with this component is ever edited,
+// so other text on screen is left alone. Keys only go to the input while it has focus
+#[derive(Component, Default)]
+pub struct ChatInput {
+    value: String,
+    // Byte offset into value, always on a char boundary
+    caret: usize,
+    focused: bool,
+    // Sent messages, oldest first
+    history: Vec<String>,
+    // Position in history while browsing it with the arrow keys, None when editing a new message
+    browsing: Option<usize>,
+    // The unsent message, restored when browsing past the newest history entry
+    draft: String,
+}
+
+impl ChatInput {
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    pub fn focus(&mut self) {
+        self.focused = true;
+    }
+
+    fn close(&mut self) {
+        self.focused = false;
+        self.set_value(String::new());
+        self.browsing = None;
+    }
+
+    fn set_value(&mut self, value: String) {
+        self.caret = value.len();
+        self.value = value;
+    }
+
+    // Stops at max_chars, anything longer would be refused by the guard after typing it all
+    fn insert(&mut self, text: &str, max_chars: usize) {
+        let room = max_chars.saturating_sub(self.value.chars().count());
+        let text = text.chars().filter(|c| !c.is_control()).take(room).collect::<String>();
+        self.value.insert_str(self.caret, &text);
+        self.caret += text.len();
+    }
+
+    fn previous_char(&self, from: usize) -> usize {
+        self.value[..from].chars().next_back().map_or(from, |c| from - c.len_utf8())
+    }
+
+    fn next_char(&self, from: usize) -> usize {
+        self.value[from..].chars().next().map_or(from, |c| from + c.len_utf8())
+    }
+
+    // Start of the word before from, skipping the whitespace in between
+    fn previous_word(&self, from: usize) -> usize {
+        let before = self.value[..from].trim_end();
+        before.rfind(char::is_whitespace).map_or(0, |i| self.next_char(i))
+    }
+
+    // End of the word after from, skipping the whitespace in between
+    fn next_word(&self, from: usize) -> usize {
+        let after = &self.value[from..];
+        let word_start = after.len() - after.trim_start().len();
+        after[word_start..].find(char::is_whitespace).map_or(self.value.len(), |i| from + word_start + i)
+    }
+
+    fn delete(&mut self, from: usize, to: usize) {
+        self.value.replace_range(from..to, "");
+        self.caret = from;
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        let index = match (self.browsing, older) {
+            (None, true) => {
+                self.draft = self.value.clone();
+                Some(self.history.len() - 1)
+            }
+            (None, false) => None,
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < self.history.len() => Some(index + 1),
+            (Some(_), false) => None,
+        };
+        self.browsing = index;
+        let value = match index {
+            Some(index) => self.history[index].clone(),
+            None => self.draft.clone(),
+        };
+        self.set_value(value);
+    }
+
+    // Takes the message out of the input, closing it and remembering the message in the history
+    fn submit(&mut self) -> Option<String> {
+        let msg = self.value.trim().to_string();
+        if msg.is_empty() {
+            return None;
+        }
+        if self.history.last() != Some(&msg) {
+            self.history.push(msg.clone());
+        }
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.close();
+        Some(msg)
+    }
+}
+
+// Bottom left input text so the player sees what they type
+fn spawn_chat_input(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // The default font has a limited number of glyphs, so use the full version for
+    // sections that will hold text input.
+    let font = asset_server.load("fonts/FiraMono-Medium.ttf");
+    let style = TextStyle {
+        font,
+        font_size: 20.0,
+        ..default()
+    };
+    let faded = TextStyle {
+        color: Color::srgba(1.0, 1.0, 1.0, 0.6),
+        ..style.clone()
+    };
+
+    // Sections: prompt, text before the caret, caret, text after the caret
+    commands.spawn((
+        TextBundle::from_sections([
+            TextSection::new(PROMPT, faded.clone()),
+            TextSection::new("", style.clone()),
+            TextSection::new(CARET, faded),
+            TextSection::new("", style),
+        ])
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(5.0),
+                left: Val::Px(5.0),
+                ..default()
+            }),
+        ChatInput::default(),
+    ));
+}
+
+// Edits the focused input. Ctrl+arrows jump words, ctrl+backspace/delete remove words and up/down go through the history.
+// Messages can be as long as the guard lets through
+pub fn edit_chat_input(
+    mut events: EventReader<KeyboardInput>,
+    key_input: Res<ButtonInput<KeyCode>>,
+    guard: Res<Guard>,
+    mut input_query: Query<&mut ChatInput>,
+    mut on_submit: EventWriter<ChatSubmittedEvent>,
+    mut on_close: EventWriter<ChatClosedEvent>,
+) {
+    let Ok(mut input) = input_query.get_single_mut() else {
+        return;
+    };
+    let ctrl = key_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let max_chars = guard.config.max_message_chars;
+    for event in events.read() {
+        if !input.focused {
+            continue;
+        }
+        // Key repeats arrive as presses too, so holding backspace keeps deleting
+        if !event.state.is_pressed() {
+            continue;
+        }
+        let caret = input.caret;
+        match &event.logical_key {
+            Key::Enter => {
+                if let Some(msg) = input.submit() {
+                    on_submit.send(ChatSubmittedEvent { msg });
+                }
+            }
+            Key::Escape => {
+                input.close();
+                on_close.send(ChatClosedEvent);
+            }
+            Key::Backspace => {
+                let from = if ctrl { input.previous_word(caret) } else { input.previous_char(caret) };
+                input.delete(from, caret);
+            }
+            Key::Delete => {
+                let to = if ctrl { input.next_word(caret) } else { input.next_char(caret) };
+                input.delete(caret, to);
+            }
+            Key::ArrowLeft => input.caret = if ctrl { input.previous_word(caret) } else { input.previous_char(caret) },
+            Key::ArrowRight => input.caret = if ctrl { input.next_word(caret) } else { input.next_char(caret) },
+            Key::Home => input.caret = 0,
+            Key::End => input.caret = input.value.len(),
+            Key::ArrowUp => input.browse_history(true),
+            Key::ArrowDown => input.browse_history(false),
+            Key::Space => input.insert(" ", max_chars),
+            // Ctrl+w deletes the previous word like in a terminal, other shortcuts are not typed.
+            // There's no paste, bevy doesn't give access to the clipboard
+            Key::Character(character) if ctrl => {
+                if character.as_str() == "w" {
+                    let from = input.previous_word(caret);
+                    input.delete(from, caret);
+                }
+            }
+            Key::Character(character) => input.insert(character, max_chars),
+            _ => continue,
+        }
+    }
+}
+
+fn render_chat_input(mut input_query: Query<(&ChatInput, &mut Text, &mut Visibility), Changed<ChatInput>>) {
+    for (input, mut text, mut visibility) in input_query.iter_mut() {
+        *visibility = if input.focused { Visibility::Inherited } else { Visibility::Hidden };
+        text.sections[1].value = input.value[..input.caret].to_string();
+        text.sections[3].value = input.value[input.caret..].to_string();
+    }
+}
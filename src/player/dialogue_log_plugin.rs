@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use bevy::{
+    app::{Plugin, Startup, Update},
+    input::{ButtonInput, mouse::{MouseScrollUnit, MouseWheel}},
+    prelude::*,
+};
+use crate::action_executor::ActionOutcomeEvent;
+use crate::npc::npc::Npc;
+use crate::player::actions_plugin::ActiveConversation;
+use crate::player::chat_input_plugin::ChatInput;
+use crate::player::speech_bubble_plugin::SpeakEvent;
+
+pub struct DialogueLogPlugin;
+
+const TOGGLE_KEY: KeyCode = KeyCode::KeyL;
+// Pixels scrolled per mouse wheel line or page key press
+const SCROLL_LINE: f32 = 20.0;
+const SCROLL_PAGE: f32 = 200.0;
+const FONT_SIZE: f32 = 16.0;
+
+impl Plugin for DialogueLogPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DialogueLog { entries: HashMap::new(), shown: None });
+        app.add_systems(Startup, spawn_dialogue_panel);
+        app.add_systems(Update, (record_dialogue, toggle_dialogue_panel, render_dialogue_log, scroll_dialogue_log).chain());
+    }
+}
+
+// Everything that was said or done, per npc. Entries of the player end up with the npc they were talking to
+#[derive(Resource)]
+pub struct DialogueLog {
+    entries: HashMap<String, Vec<LogEntry>>,
+    // Npc whose conversation the panel shows, the last one the player talked to
+    shown: Option<String>,
+}
+
+pub struct LogEntry {
+    // Time since the game started
+    pub time: f32,
+    pub kind: LogEntryKind,
+}
+
+pub enum LogEntryKind {
+    Speech {
+        speaker: String,
+        text: String,
+    },
+    // An item changing hands, or failing to
+    Action(String),
+}
+
+impl DialogueLog {
+    fn push(&mut self, npc: &str, entry: LogEntry) {
+        self.entries.entry(npc.to_string()).or_default().push(entry);
+    }
+}
+
+#[derive(Component)]
+struct DialoguePanel;
+
+// The column of entries inside the panel. Offset is how far it's scrolled up from the newest entry
+#[derive(Component, Default)]
+struct DialogueList {
+    offset: f32,
+}
+
+fn spawn_dialogue_panel(mut commands: Commands) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Px(10.0),
+                    right: Val::Px(10.0),
+                    width: Val::Px(400.0),
+                    height: Val::Percent(60.0),
+                    flex_direction: FlexDirection::Column,
+                    // Newest entries stay at the bottom, older ones overflow at the top
+                    justify_content: JustifyContent::FlexEnd,
+                    overflow: Overflow::clip_y(),
+                    padding: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                },
+                background_color: Color::srgba(0.0, 0.0, 0.0, 0.7).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            DialoguePanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(4.0),
+                        ..default()
+                    },
+                    ..default()
+                },
+                DialogueList::default(),
+            ));
+        });
+}
+
+fn record_dialogue(
+    time: Res<Time>,
+    mut log: ResMut<DialogueLog>,
+    conversation: Res<ActiveConversation>,
+    npc_query: Query<&Npc>,
+    mut on_speak: EventReader<SpeakEvent>,
+    mut on_action_outcome: EventReader<ActionOutcomeEvent>,
+) {
+    let talking_to = conversation.npc.and_then(|entity| npc_query.get(entity).ok()).map(|npc| npc.name.clone());
+    if talking_to.is_some() && log.shown != talking_to {
+        log.shown = talking_to.clone();
+    }
+    let now = time.elapsed_seconds();
+    let is_npc = |name: &str| npc_query.iter().any(|npc| npc.name == name);
+    for speech in on_speak.read() {
+        let entry = LogEntry { time: now, kind: LogEntryKind::Speech { speaker: speech.id.clone(), text: speech.text.clone() } };
+        if is_npc(&speech.id) {
+            log.push(&speech.id, entry);
+        } else if let Some(npc) = &talking_to {
+            log.push(npc, entry);
+        }
+    }
+    for outcome in on_action_outcome.read() {
+        for name in [&outcome.sender_id, &outcome.receiver_id] {
+            if is_npc(name) {
+                log.push(name, LogEntry { time: now, kind: LogEntryKind::Action(outcome.describe()) });
+            }
+        }
+    }
+}
+
+fn toggle_dialogue_panel(
+    key_input: Res<ButtonInput<KeyCode>>,
+    input_query: Query<&ChatInput>,
+    mut panel_query: Query<&mut Visibility, With<DialoguePanel>>,
+) {
+    // The key is a letter, so while typing it belongs to the message
+    if !key_input.just_pressed(TOGGLE_KEY) || input_query.iter().any(|input| input.is_focused()) {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds as u32;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Rebuilds the list whenever a new entry is logged or the player talks to someone else
+fn render_dialogue_log(
+    mut commands: Commands,
+    log: Res<DialogueLog>,
+    mut list_query: Query<(Entity, &mut DialogueList)>,
+) {
+    if !log.is_changed() {
+        return;
+    }
+    let Ok((list, mut scroll)) = list_query.get_single_mut() else {
+        return;
+    };
+    scroll.offset = 0.0;
+    commands.entity(list).despawn_descendants();
+    let entries = log.shown.as_ref().and_then(|npc| log.entries.get(npc));
+    commands.entity(list).with_children(|parent| {
+        let Some(entries) = entries else {
+            parent.spawn(TextBundle::from_section("Nothing was said yet", muted_style()));
+            return;
+        };
+        for entry in entries {
+            let time = TextSection::new(format!("[{}] ", format_time(entry.time)), muted_style());
+            let sections = match &entry.kind {
+                LogEntryKind::Speech { speaker, text } => vec![
+                    time,
+                    TextSection::new(format!("{}: ", speaker), TextStyle { font_size: FONT_SIZE, color: Color::srgb(1.0, 0.85, 0.4), ..default() }),
+                    TextSection::new(text.clone(), TextStyle { font_size: FONT_SIZE, color: Color::WHITE, ..default() }),
+                ],
+                LogEntryKind::Action(description) => vec![
+                    time,
+                    TextSection::new(description.clone(), TextStyle { font_size: FONT_SIZE, color: Color::srgb(0.5, 0.8, 1.0), ..default() }),
+                ],
+            };
+            parent.spawn(TextBundle::from_sections(sections));
+        }
+    });
+}
+
+fn muted_style() -> TextStyle {
+    TextStyle { font_size: FONT_SIZE, color: Color::srgb(0.6, 0.6, 0.6), ..default() }
+}
+
+// Mouse wheel or page up/down scroll back through older entries
+fn scroll_dialogue_log(
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut on_mouse_wheel: EventReader<MouseWheel>,
+    panel_query: Query<(&Node, &Visibility), With<DialoguePanel>>,
+    mut list_query: Query<(&mut DialogueList, &mut Style, &Node)>,
+) {
+    let Ok((panel, visibility)) = panel_query.get_single() else {
+        return;
+    };
+    let Ok((mut list, mut style, list_node)) = list_query.get_single_mut() else {
+        return;
+    };
+    if *visibility == Visibility::Hidden {
+        on_mouse_wheel.clear();
+        return;
+    }
+    let mut delta = 0.0;
+    for event in on_mouse_wheel.read() {
+        delta += match event.unit {
+            MouseScrollUnit::Line => event.y * SCROLL_LINE,
+            MouseScrollUnit::Pixel => event.y,
+        };
+    }
+    if key_input.just_pressed(KeyCode::PageUp) {
+        delta += SCROLL_PAGE;
+    }
+    if key_input.just_pressed(KeyCode::PageDown) {
+        delta -= SCROLL_PAGE;
+    }
+    let max_offset = (list_node.size().y - panel.size().y).max(0.0);
+    list.offset = (list.offset + delta).clamp(0.0, max_offset);
+    style.top = Val::Px(list.offset);
+}
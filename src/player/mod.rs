@@ -2,4 +2,7 @@ pub mod camera_plugin;
 pub mod movement_plugin;
 pub mod player_plugin;
 pub mod player;
-pub mod actions_plugin;
\ No newline at end of file
+pub mod actions_plugin;
+pub mod chat_input_plugin;
+pub mod speech_bubble_plugin;
+pub mod dialogue_log_plugin;
\ No newline at end of file
@@ -10,6 +10,9 @@ use crate::player::player::Player;
 
 pub struct PlayerMovementPlugin;
 
+// Force characters walk with, npcs walking somewhere use it too so they keep up with the player
+pub const WALK_FORCE: f32 = 15.0;
+
 impl Plugin for PlayerMovementPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_systems(PostUpdate, update_players_movement_constructor());
@@ -49,7 +52,7 @@ fn update_players_movement(
 
 fn update_player_movement(impulse: &mut ExternalForce, key_input: &ButtonInput<KeyCode>) {
     let direction = get_direction_vector(key_input);
-    impulse.force = direction * 15.0;
+    impulse.force = direction * WALK_FORCE;
 }
 
 fn get_direction_vector(key_input: &ButtonInput<KeyCode>) -> Vec3 {
@@ -1,64 +1,25 @@
-use std::collections::HashMap;
 use bevy::prelude::Component;
 use crate::character::{CharacterTrait};
-use crate::item::Item;
 
 #[derive(Component, Clone)]
 pub struct Player {
     pub(crate) name: String,
-    pub(crate) items: HashMap<Item, i32>,
 }
 
 impl Player {
     pub(crate) fn new(name: String) -> Player {
         Player {
             name,
-            items: HashMap::new(),
         }
     }
 }
 
 impl CharacterTrait for Player {
-    fn set_items(&mut self, items: HashMap<Item, i32>) {
-        self.items = items;
-    }
-
-    //noinspection DuplicatedCode
-    fn add_item(&mut self, item: Item, amount: i32) {
-        for (key, value) in &mut self.items {
-            if key.name == item.name {
-                *value += amount;
-                return;
-            }
-        }
-        self.items.insert(item, amount);
-    }
-
-
-    //noinspection DuplicatedCode
-    fn remove_item(&mut self, item: Item, amount: i32) -> bool {
-        // If player does not have the item, return false
-        if self.items.iter().position(|(key, _value)| key.name == item.name).is_none() {
-            return false;
-        }
-        self.items = self.items.clone().into_iter().filter_map(|(key, value)| {
-            if key.name != item.name {
-                return Some((key.to_owned(), value));
-            }
-            if value - amount <= 0 {
-                return None;
-            }
-            Some((key.to_owned(), value - amount))
-        }).collect();
-        true
-    }
-
-
-    fn get_items(&self) -> &HashMap<Item, i32> {
-        &self.items
+    fn get_name(&self) -> &str {
+        &self.name
     }
 
     fn print_self(&self) {
         println!("{}", self.name);
     }
-}
\ No newline at end of file
+}
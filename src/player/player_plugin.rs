@@ -6,13 +6,14 @@ use bevy::{
     pbr::StandardMaterial,
     prelude::{
         default, BuildChildren, Camera3dBundle, Commands, Mesh, OrthographicProjection,
-        ResMut, Transform,
+        ResMut, SpatialBundle, SpatialListener, Transform,
     },
     render::camera::ScalingMode,
 };
 
 use crate::character::spawn_character_entity;
 use crate::player::player::Player;
+use crate::quest::QuestLog;
 
 
 pub struct PlayerPlugin;
@@ -28,13 +29,13 @@ impl Plugin for PlayerPlugin {
 
 fn spawn_player(
     mut commands: Commands,
-    meshes: ResMut<Assets<Mesh>>,
-    materials: ResMut<Assets<StandardMaterial>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     let character = spawn_character_entity(
         &mut commands,
-        meshes,
-        materials,
+        &mut meshes,
+        &mut materials,
         Color::srgb(1.0, 0.0, 0.0),
         (0.0, 2.0, 0.0),
     );
@@ -42,11 +43,22 @@ fn spawn_player(
     commands
         .entity(character)
         .insert(Player::new("Bob".to_string()))
+        .insert(QuestLog::default())
         .with_children(|parent| {
             parent.spawn(camera);
+            parent.spawn(create_listener());
         });
 }
 
+// The player's ears. They sit at the player, not the camera high above it, so voices get louder when walking up to an npc.
+// Faces the same way as the camera so sounds on the left of the screen come out of the left speaker
+fn create_listener() -> (SpatialBundle, SpatialListener) {
+    (
+        SpatialBundle::from_transform(Transform::default().looking_to(Vec3::NEG_X, Vec3::Y)),
+        SpatialListener::default(),
+    )
+}
+
 fn create_camera() -> Camera3dBundle {
     Camera3dBundle {
         projection: OrthographicProjection {
@@ -0,0 +1,283 @@
+use std::collections::{HashMap, VecDeque};
+use std::mem;
+use bevy::{
+    app::{Plugin, Update},
+    input::ButtonInput,
+    prelude::*,
+    text::Text2dBounds,
+};
+use bevy_mod_billboard::{BillboardDepth, BillboardTextBundle};
+use bevy_mod_billboard::prelude::BillboardTextBounds;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::npc::npc::Npc;
+use crate::player::player::Player;
+
+pub struct SpeechBubblePlugin;
+
+// Scales all the 'text-bubbles' spawned above the characters
+const TEXT_SCALE: Vec3 = Vec3::splat(0.0030);
+const FONT_SIZE: f32 = 60.0;
+// Size of a bubble before scaling
+const BUBBLE_BOUNDS: Vec2 = Vec2::new(1000., 500.);
+// Rough size of a glyph at FONT_SIZE, used to work out how much text fits in BUBBLE_BOUNDS
+const GLYPH_WIDTH: f32 = FONT_SIZE * 0.55;
+const LINE_HEIGHT: f32 = FONT_SIZE * 1.25;
+// How far above a character its bubbles float
+const BUBBLE_OFFSET: f32 = 1.5;
+
+// Typewriter speed of a new page
+const REVEAL_CHARS_PER_SECOND: f32 = 40.0;
+// How long a page stays up once it's fully revealed, longer pages take longer to read
+const MIN_READ_SECONDS: f32 = 2.0;
+const READ_SECONDS_PER_CHAR: f32 = 0.06;
+
+// Reveals the current page at once, or moves on to the next page when it already is
+const SKIP_KEY: KeyCode = KeyCode::Tab;
+
+impl Plugin for SpeechBubblePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SpeakEvent>();
+        app.insert_resource(SpeechQueue { pages: HashMap::new() });
+        app.add_systems(Update, (start_speech, skip_speech, reveal_text, advance_pages).chain());
+        app.add_systems(Update, make_bubbles_follow_entities);
+    }
+}
+
+// Shows text above the player or npc named id, split into pages that fit a bubble
+#[derive(Event)]
+pub struct SpeakEvent {
+    pub id: String,
+    pub text: String,
+}
+
+// Pages that still have to be shown, per character
+#[derive(Resource)]
+struct SpeechQueue {
+    pages: HashMap<String, VecDeque<String>>,
+}
+
+// Component added to TextBundles so we can make them disappear after a while
+#[derive(Component)]
+pub struct Bubble {
+    pub id: String,
+    // Starts once the page is fully revealed
+    pub timer: Timer,
+}
+
+// Reveals the page of a bubble a few graphemes at a time
+#[derive(Component)]
+struct Typewriter {
+    page: String,
+    elapsed: f32,
+}
+
+// Splits text into pages that fit a bubble. Lines break between words, only words longer than a line are split,
+// and then between graphemes so accents and emoji stay whole
+pub fn paginate(text: &str, line_chars: usize, page_lines: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_len = 0;
+    for word in text.split_whitespace() {
+        let graphemes = word.graphemes(true).collect::<Vec<&str>>();
+        for chunk in graphemes.chunks(line_chars.max(1)) {
+            if line_len > 0 && line_len + 1 + chunk.len() > line_chars {
+                lines.push(mem::take(&mut line));
+                line_len = 0;
+            }
+            if line_len > 0 {
+                line.push(' ');
+                line_len += 1;
+            }
+            line.push_str(&chunk.concat());
+            line_len += chunk.len();
+        }
+    }
+    if line_len > 0 {
+        lines.push(line);
+    }
+    lines.chunks(page_lines.max(1)).map(|page| page.join("\n")).collect()
+}
+
+fn bubble_pages(text: &str) -> Vec<String> {
+    let line_chars = (BUBBLE_BOUNDS.x / GLYPH_WIDTH) as usize;
+    let page_lines = (BUBBLE_BOUNDS.y / LINE_HEIGHT) as usize;
+    paginate(text, line_chars, page_lines)
+}
+
+fn read_duration(page: &str) -> f32 {
+    MIN_READ_SECONDS + page.graphemes(true).count() as f32 * READ_SECONDS_PER_CHAR
+}
+
+// A bubble that shows msg right away
+pub fn create_text_bundle(id: String, msg: String, transform: &Transform) -> (BillboardTextBundle, Bubble) {
+    let timer = Timer::from_seconds(read_duration(&msg), TimerMode::Once);
+    (BillboardTextBundle {
+        transform: transform.with_scale(TEXT_SCALE),
+        text: Text::from_section(
+            msg,
+            TextStyle {
+                font_size: FONT_SIZE,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+            .with_justify(JustifyText::Center),
+        billboard_depth: BillboardDepth(false),
+        text_bounds: BillboardTextBounds(Text2dBounds { size: BUBBLE_BOUNDS }),
+        ..default()
+    }, Bubble { id, timer })
+}
+
+// Where the bubbles of the character named id go, None when there is no such character
+pub fn bubble_position(id: &str, player_query: &Query<(&Player, &Transform)>, npc_query: &Query<(&Npc, &Transform)>) -> Option<Transform> {
+    let transform = player_query
+        .iter()
+        .find(|(player, _)| player.name == id)
+        .map(|(_, transform)| transform)
+        .or_else(|| npc_query.iter().find(|(npc, _)| npc.name == id).map(|(_, transform)| transform))?;
+    Some(Transform::from_translation(transform.translation + Vec3::Y * BUBBLE_OFFSET))
+}
+
+// Starts out empty for the typewriter, but stays up as long as the whole page takes to read
+fn spawn_page(commands: &mut Commands, id: &str, page: String, transform: &Transform) {
+    let (text, mut bubble) = create_text_bundle(id.to_string(), String::new(), transform);
+    bubble.timer = Timer::from_seconds(read_duration(&page), TimerMode::Once);
+    commands.spawn((text, bubble, Typewriter { page, elapsed: 0. }));
+}
+
+// Replaces whatever the character was saying with the new text
+fn start_speech(
+    mut commands: Commands,
+    mut on_speak: EventReader<SpeakEvent>,
+    mut queue: ResMut<SpeechQueue>,
+    bubbles: Query<(Entity, &Bubble)>,
+    player_query: Query<(&Player, &Transform)>,
+    npc_query: Query<(&Npc, &Transform)>,
+) {
+    for speech in on_speak.read() {
+        let Some(transform) = bubble_position(&speech.id, &player_query, &npc_query) else {
+            continue;
+        };
+        for (entity, bubble) in bubbles.iter() {
+            if bubble.id == speech.id {
+                commands.entity(entity).despawn();
+            }
+        }
+        let mut pages = VecDeque::from(bubble_pages(&speech.text));
+        let Some(first) = pages.pop_front() else {
+            continue;
+        };
+        spawn_page(&mut commands, &speech.id, first, &transform);
+        queue.pages.insert(speech.id.clone(), pages);
+    }
+}
+
+fn skip_speech(
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut typewriters: Query<&mut Typewriter>,
+    mut bubbles: Query<&mut Bubble, Without<Typewriter>>,
+) {
+    if !key_input.just_pressed(SKIP_KEY) {
+        return;
+    }
+    if typewriters.is_empty() {
+        for mut bubble in bubbles.iter_mut() {
+            let remaining = bubble.timer.remaining();
+            bubble.timer.tick(remaining);
+        }
+    } else {
+        for mut typewriter in typewriters.iter_mut() {
+            typewriter.elapsed = f32::MAX;
+        }
+    }
+}
+
+fn reveal_text(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut typewriters: Query<(Entity, &mut Text, &mut Typewriter)>,
+) {
+    for (entity, mut text, mut typewriter) in typewriters.iter_mut() {
+        typewriter.elapsed += time.delta_seconds();
+        let revealed = (typewriter.elapsed * REVEAL_CHARS_PER_SECOND) as usize;
+        text.sections[0].value = typewriter.page.graphemes(true).take(revealed).collect();
+        if revealed >= typewriter.page.graphemes(true).count() {
+            commands.entity(entity).remove::<Typewriter>();
+        }
+    }
+}
+
+// Despawns bubbles that were up long enough and shows the next page of their character, if any
+fn advance_pages(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut queue: ResMut<SpeechQueue>,
+    mut bubbles: Query<(Entity, &Transform, &mut Bubble), Without<Typewriter>>,
+) {
+    for (entity, transform, mut bubble) in bubbles.iter_mut() {
+        if !bubble.timer.tick(time.delta()).finished() {
+            continue;
+        }
+        commands.entity(entity).despawn();
+        if let Some(page) = queue.pages.get_mut(&bubble.id).and_then(|pages| pages.pop_front()) {
+            spawn_page(&mut commands, &bubble.id, page, transform);
+        }
+    }
+}
+
+// Makes sure the text-bubbles stick to the characters when they move
+fn make_bubbles_follow_entities(
+    player_query: Query<(&Player, &Transform)>,
+    npc_query: Query<(&Npc, &Transform)>,
+    mut bubble_query: Query<(&mut Transform, &Bubble), (Without<Player>, Without<Npc>)>,
+) {
+    for (mut bubble_transform, bubble) in bubble_query.iter_mut() {
+        if let Some(position) = bubble_position(&bubble.id, &player_query, &npc_query) {
+            bubble_transform.translation = position.translation;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str, line_chars: usize) -> Vec<String> {
+        paginate(text, line_chars, usize::MAX).join("\n").lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn breaks_lines_between_words() {
+        assert_eq!(lines("the quick brown fox jumps", 10), ["the quick", "brown fox", "jumps"]);
+        assert_eq!(lines("  spaced \n out  ", 20), ["spaced out"]);
+        assert!(paginate("", 10, 2).is_empty());
+    }
+
+    #[test]
+    fn accents_count_as_one_character() {
+        // Precomposed and combining accents alike
+        assert_eq!(lines("café crème brûlée", 10), ["café crème", "brûlée"]);
+        assert_eq!(lines("cafe\u{301} cre\u{300}me", 10), ["cafe\u{301} cre\u{300}me"]);
+        assert_eq!(lines("e\u{301}e\u{301}e\u{301}", 2), ["e\u{301}e\u{301}", "e\u{301}"]);
+    }
+
+    #[test]
+    fn emoji_are_never_split() {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        assert_eq!(lines(&format!("{} 👍🏽 ok", family), 4), [format!("{} 👍🏽", family), "ok".to_string()]);
+        assert_eq!(lines("😀😀😀😀😀", 2), ["😀😀", "😀😀", "😀"]);
+    }
+
+    #[test]
+    fn splits_words_longer_than_a_line() {
+        assert_eq!(lines("a abcdefghij b", 4), ["a", "abcd", "efgh", "ij b"]);
+        assert_eq!(lines("Donaudampfschifffahrt", 8), ["Donaudam", "pfschiff", "fahrt"]);
+    }
+
+    #[test]
+    fn groups_lines_into_pages() {
+        assert_eq!(paginate("one two three four five", 5, 2), ["one\ntwo", "three\nfour", "five"]);
+        // Degenerate bubbles still show everything
+        assert_eq!(paginate("ab", 0, 0), ["a", "b"]);
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use bevy::app::{App, Plugin, Update};
+use bevy::asset::io::file::FileAssetReader;
+use bevy::log::info;
+use bevy::math::Vec2;
+use bevy::prelude::{Component, Query, Res, Resource, Transform};
+use serde::{Deserialize, Serialize};
+use crate::inventory::{Inventory, InventoryError};
+use crate::item_registry::{ItemLookupError, ItemRegistry};
+use crate::navigation::Locations;
+use crate::npc::npc::Npc;
+use crate::npc::npc_definition::InventoryEntry;
+use crate::player::actions_plugin::ActiveConversation;
+use crate::player::player::Player;
+
+// Relative to the folder the assets folder lives in
+const QUESTS_FILE: &str = "assets/quests.ron";
+
+// How close the player has to get to a location to reach it
+const REACH_DISTANCE: f32 = 1.5;
+
+pub struct QuestPlugin;
+
+impl Plugin for QuestPlugin {
+    fn build(&self, app: &mut App) {
+        let path = FileAssetReader::get_base_path().join(QUESTS_FILE);
+        let data = fs::read_to_string(&path).unwrap_or_else(|err| panic!("Could not read {}: {}", path.display(), err));
+        // Quests are checked against the items and locations while loading, so those have to be loaded first
+        let item_registry = app.world().get_resource::<ItemRegistry>().expect("QuestPlugin needs ItemRegistryPlugin to be added before it");
+        let locations = app.world().get_resource::<Locations>().expect("QuestPlugin needs NavigationPlugin to be added before it");
+        let registry = QuestRegistry::from_ron(&data, item_registry, locations)
+            .unwrap_or_else(|err| panic!("Invalid {}: {}", path.display(), err));
+        app.insert_resource(registry);
+        app.add_systems(Update, track_objectives);
+    }
+}
+
+// What the player has to do before the giver hands out the reward
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Objective {
+    // Bring items to the giver, they're handed over when the quest is completed
+    Fetch {
+        item: String,
+        amount: i32,
+    },
+    TalkTo {
+        npc: String,
+    },
+    Reach {
+        location: String,
+    },
+}
+
+impl Display for Objective {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Objective::Fetch { item, amount } => write!(f, "bring {} {}", amount, item),
+            Objective::TalkTo { npc } => write!(f, "talk to {}", npc),
+            Objective::Reach { location } => write!(f, "go to {}", location),
+        }
+    }
+}
+
+// A quest from assets/quests.ron. The model can only hand out these, so every quest is one the game can track
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuestTemplate {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    // Names of the npcs that offer it, anyone can when empty
+    #[serde(default)]
+    pub givers: Vec<String>,
+    pub objective: Objective,
+    // Paid from the giver's inventory
+    pub reward: Vec<InventoryEntry>,
+}
+
+impl QuestTemplate {
+    pub fn offered_by(&self, giver: &str) -> bool {
+        self.givers.is_empty() || self.givers.iter().any(|name| name.eq_ignore_ascii_case(giver))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum QuestError {
+    Unknown {
+        query: String,
+        available: Vec<String>,
+    },
+    NotOffered {
+        quest: String,
+        giver: String,
+    },
+    AlreadyTaken(String),
+    NotTaken(String),
+    NotDone {
+        quest: String,
+        objective: String,
+    },
+    UnknownItem(ItemLookupError),
+    Inventory(InventoryError),
+}
+
+impl Display for QuestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuestError::Unknown { query, available } if available.is_empty() => write!(f, "there is no quest called '{}'", query),
+            QuestError::Unknown { query, available } => write!(f, "there is no quest called '{}', available quests are {}", query, available.join(", ")),
+            QuestError::NotOffered { quest, giver } => write!(f, "{} doesn't offer the quest '{}'", giver, quest),
+            QuestError::AlreadyTaken(quest) => write!(f, "the quest '{}' was already taken", quest),
+            QuestError::NotTaken(quest) => write!(f, "the quest '{}' isn't active", quest),
+            QuestError::NotDone { quest, objective } => write!(f, "the quest '{}' isn't done yet, still have to {}", quest, objective),
+            QuestError::UnknownItem(err) => write!(f, "{}", err),
+            QuestError::Inventory(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for QuestError {}
+
+// Every quest template, keyed by id
+#[derive(Resource)]
+pub struct QuestRegistry {
+    templates: HashMap<String, QuestTemplate>,
+}
+
+impl QuestRegistry {
+    // Parses a list of quests and checks that ids are unique and every item and location they mention exists
+    pub fn from_ron(data: &str, item_registry: &ItemRegistry, locations: &Locations) -> Result<QuestRegistry, String> {
+        let list = ron::from_str::<Vec<QuestTemplate>>(data).map_err(|err| err.to_string())?;
+        let mut templates = HashMap::new();
+        for mut template in list {
+            let id = template.id.clone();
+            // Stored by item id, so the model and the save files see the same names whatever was written in the file
+            let resolve = |item: &mut String| -> Result<(), String> {
+                *item = item_registry.resolve(item).map_err(|err| format!("quest {}: {}", id, err))?.id.clone();
+                Ok(())
+            };
+            if let Objective::Fetch { item, .. } = &mut template.objective {
+                resolve(item)?;
+            }
+            for reward in template.reward.iter_mut() {
+                resolve(&mut reward.item)?;
+            }
+            if let Objective::Reach { location } = &template.objective {
+                if locations.get(location).is_none() {
+                    return Err(format!("quest {}: there is no location called '{}'", template.id, location));
+                }
+            }
+            if let Some(duplicate) = templates.insert(template.id.clone(), template) {
+                return Err(format!("quest id {} is used twice", duplicate.id));
+            }
+        }
+        Ok(QuestRegistry { templates })
+    }
+
+    // Finds a quest by id or title, the model tends to use either
+    pub fn resolve(&self, query: &str) -> Result<&QuestTemplate, QuestError> {
+        let wanted = query.trim().to_lowercase();
+        self.templates
+            .values()
+            .find(|template| template.id == wanted.replace(' ', "_") || template.title.to_lowercase() == wanted)
+            .ok_or_else(|| QuestError::Unknown { query: query.to_string(), available: self.templates.keys().cloned().collect() })
+    }
+
+    pub fn offered_by<'a>(&'a self, giver: &'a str) -> impl Iterator<Item = &'a QuestTemplate> {
+        self.templates.values().filter(move |template| template.offered_by(giver))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum QuestStatus {
+    Active,
+    Completed,
+}
+
+// A quest the player took, a copy of its template so editing quests.ron doesn't change quests that were already given
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quest {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub giver: String,
+    pub objective: Objective,
+    pub reward: Vec<InventoryEntry>,
+    pub status: QuestStatus,
+    // Whether the player did what the objective asks and can turn the quest in
+    pub objective_met: bool,
+}
+
+impl Quest {
+    fn matches(&self, query: &str) -> bool {
+        let wanted = query.trim().to_lowercase();
+        self.id == wanted.replace(' ', "_") || self.title.to_lowercase() == wanted
+    }
+}
+
+// The quests of a player, both active and completed
+#[derive(Component, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuestLog {
+    quests: Vec<Quest>,
+}
+
+impl QuestLog {
+    // Adds a quest from the template, quests can only be taken once
+    pub fn take(&mut self, template: &QuestTemplate, giver: &str) -> Result<(), QuestError> {
+        if !template.offered_by(giver) {
+            return Err(QuestError::NotOffered { quest: template.title.clone(), giver: giver.to_string() });
+        }
+        if self.quests.iter().any(|quest| quest.id == template.id) {
+            return Err(QuestError::AlreadyTaken(template.title.clone()));
+        }
+        self.quests.push(Quest {
+            id: template.id.clone(),
+            title: template.title.clone(),
+            description: template.description.clone(),
+            giver: giver.to_string(),
+            objective: template.objective.clone(),
+            reward: template.reward.clone(),
+            status: QuestStatus::Active,
+            objective_met: false,
+        });
+        Ok(())
+    }
+
+    // The active quest given by giver that matches query
+    pub fn active_mut(&mut self, giver: &str, query: &str) -> Result<&mut Quest, QuestError> {
+        self.quests
+            .iter_mut()
+            .find(|quest| quest.status == QuestStatus::Active && quest.giver == giver && quest.matches(query))
+            .ok_or_else(|| QuestError::NotTaken(query.to_string()))
+    }
+}
+
+// Checks the giver can pay the reward before a quest is handed out
+pub fn check_reward(template: &QuestTemplate, item_registry: &ItemRegistry, giver: &Inventory) -> Result<(), QuestError> {
+    for reward in &template.reward {
+        let item = item_registry.resolve(&reward.item).map_err(QuestError::UnknownItem)?;
+        giver.can_remove(item, reward.amount).map_err(QuestError::Inventory)?;
+    }
+    Ok(())
+}
+
+// Hands the fetched items to the giver and the reward to the player, then marks the quest completed.
+// Either everything moves or, when anything can't, nothing does
+pub fn turn_in(quest: &mut Quest, item_registry: &ItemRegistry, player: &mut Inventory, giver: &mut Inventory) -> Result<(), QuestError> {
+    if !quest.objective_met {
+        return Err(QuestError::NotDone { quest: quest.title.clone(), objective: quest.objective.to_string() });
+    }
+    let (mut player_after, mut giver_after) = (player.clone(), giver.clone());
+    if let Objective::Fetch { item, amount } = &quest.objective {
+        let item = item_registry.resolve(item).map_err(QuestError::UnknownItem)?;
+        Inventory::transfer(&mut player_after, &mut giver_after, item, *amount).map_err(QuestError::Inventory)?;
+    }
+    for reward in &quest.reward {
+        let item = item_registry.resolve(&reward.item).map_err(QuestError::UnknownItem)?;
+        Inventory::transfer(&mut giver_after, &mut player_after, item, reward.amount).map_err(QuestError::Inventory)?;
+    }
+    *player = player_after;
+    *giver = giver_after;
+    quest.status = QuestStatus::Completed;
+    Ok(())
+}
+
+// A quest the npc could hand out, as the model sees it in the NpcContext
+#[derive(Serialize)]
+pub struct QuestOffer {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub objective: String,
+    pub reward: String,
+}
+
+// A quest the npc gave to the one it's talking to
+#[derive(Serialize)]
+pub struct QuestState {
+    pub id: String,
+    pub title: String,
+    pub objective: String,
+    pub status: QuestStatus,
+    pub objective_met: bool,
+}
+
+// Which quests the npc can still give and which ones were already agreed on. Empty when talking to another npc
+#[derive(Serialize, Default)]
+pub struct QuestContext {
+    pub offers: Vec<QuestOffer>,
+    pub taken: Vec<QuestState>,
+}
+
+impl QuestContext {
+    pub fn new(registry: &QuestRegistry, giver: &str, log: &QuestLog) -> QuestContext {
+        let describe_reward = |reward: &[InventoryEntry]| reward.iter().map(|entry| format!("{} {}", entry.amount, entry.item)).collect::<Vec<_>>().join(", ");
+        QuestContext {
+            offers: registry
+                .offered_by(giver)
+                .filter(|template| !log.quests.iter().any(|quest| quest.id == template.id))
+                .map(|template| QuestOffer {
+                    id: template.id.clone(),
+                    title: template.title.clone(),
+                    description: template.description.clone(),
+                    objective: template.objective.to_string(),
+                    reward: describe_reward(&template.reward),
+                })
+                .collect(),
+            taken: log
+                .quests
+                .iter()
+                .filter(|quest| quest.giver == giver)
+                .map(|quest| QuestState {
+                    id: quest.id.clone(),
+                    title: quest.title.clone(),
+                    objective: quest.objective.to_string(),
+                    status: quest.status,
+                    objective_met: quest.objective_met,
+                })
+                .collect(),
+        }
+    }
+}
+
+// Fetch objectives follow the player's inventory, talking to the npc or reaching the place is enough for the others
+fn track_objectives(
+    locations: Res<Locations>,
+    conversation: Res<ActiveConversation>,
+    npc_query: Query<&Npc>,
+    mut player_query: Query<(&Player, &Transform, &Inventory, &mut QuestLog)>,
+) {
+    let talking_to = conversation.npc.and_then(|entity| npc_query.get(entity).ok()).map(|npc| npc.name.as_str());
+    for (player, transform, inventory, mut log) in player_query.iter_mut() {
+        let position = Vec2::new(transform.translation.x, transform.translation.z);
+        let met = log
+            .quests
+            .iter()
+            .map(|quest| match &quest.objective {
+                _ if quest.status == QuestStatus::Completed => quest.objective_met,
+                Objective::Fetch { item, amount } => inventory.count(item) >= *amount,
+                Objective::TalkTo { npc } => quest.objective_met || talking_to.is_some_and(|name| name.eq_ignore_ascii_case(npc)),
+                Objective::Reach { location } => {
+                    quest.objective_met || locations.get(location).is_some_and(|place| place.distance(position) <= REACH_DISTANCE)
+                }
+            })
+            .collect::<Vec<_>>();
+        // Only touch the log when something changed, so change detection stays meaningful
+        if log.quests.iter().zip(&met).all(|(quest, met)| quest.objective_met == *met) {
+            continue;
+        }
+        for (quest, met) in log.quests.iter_mut().zip(met) {
+            if met && !quest.objective_met {
+                info!("{} can turn in {} to {}", player.name, quest.title, quest.giver);
+            }
+            quest.objective_met = met;
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bevy::app::{App, Plugin, Startup, Update};
+use bevy::input::ButtonInput;
+use bevy::log::{info, warn};
+use bevy::prelude::{Commands, Entity, Event, EventReader, EventWriter, KeyCode, Query, Res, ResMut, Resource, Time, Timer, TimerMode, Transform, Visibility, With, Without};
+use bevy_rapier3d::prelude::{ColliderDisabled, RigidBodyDisabled};
+use serde::{Deserialize, Serialize};
+use crate::character::Character;
+use crate::clock::GameClock;
+use crate::communication::ChatMessage;
+use crate::inventory::Inventory;
+use crate::item::Item;
+use crate::item_registry::ItemRegistry;
+use crate::navigation::NavPath;
+use crate::npc::follow::Following;
+use crate::npc::npc::Npc;
+use crate::npc::npc_plugin::NpcRoster;
+use crate::npc::relationship::Relationships;
+use crate::npc::resupply::Resupplying;
+use crate::player::player::Player;
+use crate::quest::QuestLog;
+use crate::trade::Trades;
+
+// Bump when the layout of SaveFile changes, older files are refused instead of half loaded
+const SAVE_VERSION: u32 = 2;
+const SAVE_DIR: &str = "saves";
+const SLOT_COUNT: u8 = 4;
+const AUTOSAVE_SECONDS: f32 = 60.0;
+
+// F1-F4 pick the slot, F5 saves to it and F9 loads it. The active slot is loaded on start and autosaved to
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveGameEvent>();
+        app.add_event::<LoadGameEvent>();
+        app.insert_resource(SaveSlots {
+            active: 1,
+            autosave: Timer::from_seconds(AUTOSAVE_SECONDS, TimerMode::Repeating),
+        });
+        app.add_systems(Startup, load_on_start);
+        app.add_systems(Update, (listen_save_keys, autosave, save_game, load_game, apply_pending_load));
+    }
+}
+
+#[derive(Resource)]
+pub struct SaveSlots {
+    pub active: u8,
+    autosave: Timer,
+}
+
+#[derive(Event)]
+pub struct SaveGameEvent {
+    pub slot: u8,
+}
+
+#[derive(Event)]
+pub struct LoadGameEvent {
+    pub slot: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveFile {
+    version: u32,
+    // Seconds since the unix epoch
+    saved_at: u64,
+    // In-game minutes on the GameClock, missing in saves made before the clock existed
+    #[serde(default)]
+    clock_minutes: Option<f64>,
+    players: Vec<PlayerSave>,
+    npcs: Vec<NpcSave>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CharacterSave {
+    name: String,
+    position: (f32, f32, f32),
+    inventory: Vec<ItemSave>,
+}
+
+// Items are saved by id and looked up in the ItemRegistry on load, so changed item definitions apply to old saves
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ItemSave {
+    item: String,
+    amount: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlayerSave {
+    #[serde(flatten)]
+    character: CharacterSave,
+    // Missing in saves made before quests existed
+    #[serde(default)]
+    quests: QuestLog,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NpcSave {
+    #[serde(flatten)]
+    character: CharacterSave,
+    message_history: Vec<ChatMessage>,
+    // Missing in saves made before relationships existed
+    #[serde(default)]
+    relationships: Relationships,
+}
+
+// A loaded save that still has to be applied. Npcs are spawned once their definitions are loaded,
+// so their state is applied as soon as they show up. Whatever is left once every npc is there no longer exists
+#[derive(Resource)]
+struct PendingLoad {
+    players: HashMap<String, PlayerSave>,
+    npcs: HashMap<String, NpcSave>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Version {
+        found: u32,
+    },
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "{}", err),
+            SaveError::Json(err) => write!(f, "save file is corrupt: {}", err),
+            SaveError::Version { found } => write!(f, "save file version {} can't be loaded, expected {}", found, SAVE_VERSION),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+fn slot_path(slot: u8) -> PathBuf {
+    PathBuf::from(SAVE_DIR).join(format!("slot_{}.json", slot))
+}
+
+fn write_save(slot: u8, save: &SaveFile) -> Result<(), SaveError> {
+    fs::create_dir_all(SAVE_DIR).map_err(SaveError::Io)?;
+    let json = serde_json::to_string_pretty(save).map_err(SaveError::Json)?;
+    // Write next to the slot first so a crash halfway doesn't destroy the previous save
+    let tmp = slot_path(slot).with_extension("json.tmp");
+    fs::write(&tmp, json).map_err(SaveError::Io)?;
+    fs::rename(&tmp, slot_path(slot)).map_err(SaveError::Io)
+}
+
+fn read_save(slot: u8) -> Result<SaveFile, SaveError> {
+    let json = fs::read_to_string(slot_path(slot)).map_err(SaveError::Io)?;
+    let save = serde_json::from_str::<SaveFile>(&json).map_err(SaveError::Json)?;
+    if save.version != SAVE_VERSION {
+        return Err(SaveError::Version { found: save.version });
+    }
+    Ok(save)
+}
+
+fn load_on_start(mut on_load: EventWriter<LoadGameEvent>, slots: Res<SaveSlots>) {
+    if slot_path(slots.active).exists() {
+        on_load.send(LoadGameEvent { slot: slots.active });
+    }
+}
+
+fn listen_save_keys(
+    key_input: Res<ButtonInput<KeyCode>>,
+    mut slots: ResMut<SaveSlots>,
+    mut on_save: EventWriter<SaveGameEvent>,
+    mut on_load: EventWriter<LoadGameEvent>,
+) {
+    let slot_keys = [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4];
+    for (slot, key) in (1..=SLOT_COUNT).zip(slot_keys) {
+        if key_input.just_pressed(key) {
+            slots.active = slot;
+            info!("Save slot {} selected", slot);
+        }
+    }
+    if key_input.just_pressed(KeyCode::F5) {
+        on_save.send(SaveGameEvent { slot: slots.active });
+    }
+    if key_input.just_pressed(KeyCode::F9) {
+        on_load.send(LoadGameEvent { slot: slots.active });
+    }
+}
+
+fn autosave(time: Res<Time>, mut slots: ResMut<SaveSlots>, mut on_save: EventWriter<SaveGameEvent>) {
+    if slots.autosave.tick(time.delta()).just_finished() {
+        let slot = slots.active;
+        on_save.send(SaveGameEvent { slot });
+    }
+}
+
+// Open offers aren't saved, what's held in escrow is saved as still belonging to whoever offered it
+fn save_game(
+    clock: Res<GameClock>,
+    trades: Res<Trades>,
+    player_query: Query<(Entity, &Player, &Transform, &Inventory, &QuestLog)>,
+    npc_query: Query<(Entity, &Npc, &Transform, &Inventory, &Relationships), Without<Player>>,
+    mut on_save: EventReader<SaveGameEvent>,
+) {
+    for req in on_save.read() {
+        let save = SaveFile {
+            version: SAVE_VERSION,
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            clock_minutes: Some(clock.minutes()),
+            players: player_query
+                .iter()
+                .map(|(entity, player, transform, inventory, quests)| PlayerSave {
+                    character: character_save(&player.name, transform, inventory, trades.escrowed(entity)),
+                    quests: quests.clone(),
+                })
+                .collect(),
+            npcs: npc_query
+                .iter()
+                .map(|(entity, npc, transform, inventory, relationships)| NpcSave {
+                    character: character_save(&npc.name, transform, inventory, trades.escrowed(entity)),
+                    message_history: npc.message_history.clone(),
+                    relationships: relationships.clone(),
+                })
+                .collect(),
+        };
+        match write_save(req.slot, &save) {
+            Ok(()) => info!("Saved the game to slot {}", req.slot),
+            Err(err) => warn!("Could not save to slot {}: {}", req.slot, err),
+        }
+    }
+}
+
+fn character_save<'a>(name: &str, transform: &Transform, inventory: &Inventory, escrowed: impl Iterator<Item = (&'a Item, &'a i32)>) -> CharacterSave {
+    let mut inventory = inventory.clone();
+    for (item, amount) in escrowed {
+        if let Err(err) = inventory.add(item, *amount) {
+            warn!("Could not save {} {} held in escrow for {}: {}", amount, item.name, name, err);
+        }
+    }
+    let mut items = inventory
+        .items()
+        .iter()
+        .map(|(item, amount)| ItemSave { item: item.id.clone(), amount: *amount })
+        .collect::<Vec<_>>();
+    // Keeps save files stable between saves of the same state
+    items.sort_by(|a, b| a.item.cmp(&b.item));
+    CharacterSave {
+        name: name.to_string(),
+        position: transform.translation.into(),
+        inventory: items,
+    }
+}
+
+// Whatever characters were on their way to is part of the state being replaced, they stand still at their loaded position
+fn load_game(
+    mut commands: Commands,
+    mut clock: ResMut<GameClock>,
+    mut trades: ResMut<Trades>,
+    mut inventory_query: Query<&mut Inventory>,
+    character_query: Query<Entity, With<Character>>,
+    resupplying_query: Query<Entity, With<Resupplying>>,
+    mut on_load: EventReader<LoadGameEvent>,
+) {
+    for req in on_load.read() {
+        match read_save(req.slot) {
+            Ok(save) => {
+                info!("Loading slot {}", req.slot);
+                // Offers made before loading would hand their escrow back into the loaded inventories later on
+                trades.cancel_all(&mut inventory_query);
+                for entity in character_query.iter() {
+                    commands.entity(entity).remove::<(Following, NavPath)>();
+                }
+                // Npcs that are away are hidden and can't be bumped into until they return
+                for entity in resupplying_query.iter() {
+                    commands.entity(entity).remove::<(Resupplying, ColliderDisabled, RigidBodyDisabled)>().insert(Visibility::Inherited);
+                }
+                if let Some(minutes) = save.clock_minutes {
+                    clock.set_minutes(minutes);
+                }
+                commands.insert_resource(PendingLoad {
+                    players: save.players.into_iter().map(|p| (p.character.name.clone(), p)).collect(),
+                    npcs: save.npcs.into_iter().map(|n| (n.character.name.clone(), n)).collect(),
+                });
+            }
+            Err(err) => warn!("Could not load slot {}: {}", req.slot, err),
+        }
+    }
+}
+
+fn apply_pending_load(
+    mut commands: Commands,
+    pending: Option<ResMut<PendingLoad>>,
+    roster: NpcRoster,
+    item_registry: Res<ItemRegistry>,
+    mut player_query: Query<(&Player, &mut Transform, &mut Inventory, &mut QuestLog)>,
+    mut npc_query: Query<(&mut Npc, &mut Transform, &mut Inventory, &mut Relationships), Without<Player>>,
+) {
+    let Some(mut pending) = pending else {
+        return;
+    };
+    for (player, mut transform, mut inventory, mut quests) in player_query.iter_mut() {
+        if let Some(save) = pending.players.remove(&player.name) {
+            apply_character(save.character, &mut transform, &mut inventory, &item_registry);
+            *quests = save.quests;
+        }
+    }
+    for (mut npc, mut transform, mut inventory, mut relationships) in npc_query.iter_mut() {
+        if let Some(save) = pending.npcs.remove(&npc.name) {
+            apply_character(save.character, &mut transform, &mut inventory, &item_registry);
+            // The system prompt comes from the npc definition, which may have changed since saving
+            let mut history = save.message_history;
+            if let (Some(saved_prompt), Some(prompt)) = (history.first_mut(), npc.message_history.first()) {
+                *saved_prompt = prompt.clone();
+            }
+            npc.message_history = history;
+            npc.history_generation = npc.history_generation.wrapping_add(1);
+            *relationships = save.relationships;
+        }
+    }
+    if (pending.players.is_empty() && pending.npcs.is_empty()) || roster.complete() {
+        for name in pending.players.keys() {
+            warn!("Saved player {} doesn't exist anymore, its state is not loaded", name);
+        }
+        for name in pending.npcs.keys() {
+            warn!("Saved npc {} doesn't exist anymore, its state is not loaded", name);
+        }
+        commands.remove_resource::<PendingLoad>();
+    }
+}
+
+fn apply_character(save: CharacterSave, transform: &mut Transform, inventory: &mut Inventory, item_registry: &ItemRegistry) {
+    transform.translation = save.position.into();
+    // Limits come from the current definitions, only the contents are restored
+    let mut loaded = Inventory::new(inventory.capacity, inventory.max_weight);
+    for ItemSave { item, amount } in save.inventory {
+        let Some(found) = item_registry.get(&item) else {
+            warn!("Saved item {} of {} doesn't exist anymore, {} of it are lost", item, save.name, amount);
+            continue;
+        };
+        if let Err(err) = loaded.add(found, amount) {
+            warn!("Could not load {} {} of {}: {}", amount, found.name, save.name, err);
+        }
+    }
+    *inventory = loaded;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> ItemRegistry {
+        ItemRegistry::from_ron(include_str!("../assets/items.ron")).unwrap()
+    }
+
+    // What comes out of a save file written for the character
+    fn round_trip(name: &str, transform: &Transform, inventory: &Inventory, escrowed: &[(Item, i32)]) -> CharacterSave {
+        let save = SaveFile {
+            version: SAVE_VERSION,
+            saved_at: 0,
+            clock_minutes: Some(600.0),
+            players: vec![PlayerSave {
+                character: character_save(name, transform, inventory, escrowed.iter().map(|(item, amount)| (item, amount))),
+                quests: QuestLog::default(),
+            }],
+            npcs: Vec::new(),
+        };
+        let json = serde_json::to_string(&save).unwrap();
+        let mut loaded = serde_json::from_str::<SaveFile>(&json).unwrap();
+        assert_eq!(loaded.clock_minutes, Some(600.0));
+        loaded.players.remove(0).character
+    }
+
+    #[test]
+    fn characters_survive_a_round_trip() {
+        let registry = registry();
+        let (bread, coin) = (registry.get("bread").unwrap(), registry.get("gold_coin").unwrap());
+        let mut inventory = Inventory::new(Some(100), None);
+        inventory.add(bread, 3).unwrap();
+        inventory.add(coin, 20).unwrap();
+        let transform = Transform::from_xyz(1.0, 2.0, 3.0);
+
+        // Coins held in escrow for an open offer are saved as still belonging to the character
+        let save = round_trip("Hero", &transform, &inventory, &[(coin.clone(), 5)]);
+        assert_eq!(save.inventory, vec![
+            ItemSave { item: "bread".to_string(), amount: 3 },
+            ItemSave { item: "gold_coin".to_string(), amount: 25 },
+        ]);
+
+        let mut loaded_transform = Transform::default();
+        let mut loaded = Inventory::new(Some(50), Some(500));
+        apply_character(save, &mut loaded_transform, &mut loaded, &registry);
+        assert_eq!(loaded_transform.translation, transform.translation);
+        assert_eq!((loaded.count("bread"), loaded.count("gold_coin")), (3, 25));
+        assert_eq!((loaded.capacity, loaded.max_weight), (Some(50), Some(500)));
+    }
+
+    #[test]
+    fn unknown_saved_items_are_skipped() {
+        let save = CharacterSave {
+            name: "Hero".to_string(),
+            position: (0.0, 0.0, 0.0),
+            inventory: vec![
+                ItemSave { item: "dragon_egg".to_string(), amount: 1 },
+                ItemSave { item: "bread".to_string(), amount: 2 },
+            ],
+        };
+        let mut inventory = Inventory::new(None, None);
+        apply_character(save, &mut Transform::default(), &mut inventory, &registry());
+        assert_eq!(inventory.total_count(), 2);
+        assert_eq!(inventory.count("bread"), 2);
+    }
+}
@@ -0,0 +1,459 @@
+use std::fmt::{Display, Formatter};
+use bevy::app::{App, Plugin, Update};
+use bevy::ecs::system::SystemParam;
+use bevy::log::{info, warn};
+use bevy::prelude::{Entity, Event, EventWriter, Query, Res, ResMut, Resource, Time};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use crate::Action;
+use crate::inventory::{Inventory, InventoryError};
+use crate::item::Item;
+use crate::item_registry::{ItemLookupError, ItemRegistry};
+use crate::npc::npc::Npc;
+use crate::npc::relationship::Relationships;
+
+// Offers nobody answered are called off after this long and the escrow goes back
+const TRADE_TIMEOUT_SECONDS: f32 = 300.0;
+// Npcs can hand out small gifts with Give, anything worth more has to be traded
+pub const MAX_GIFT_VALUE: i32 = 5;
+// Used for npcs whose definition doesn't set a margin
+pub const DEFAULT_MIN_MARGIN_PERCENT: i32 = 10;
+// Most of one item that can change hands at once, anything above is refused before any math is done on it
+pub const MAX_AMOUNT: i32 = 10_000;
+
+pub struct TradePlugin;
+
+impl Plugin for TradePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TradeCompletedEvent>();
+        app.insert_resource(Trades { pending: Vec::new() });
+        app.add_systems(Update, expire_trades);
+    }
+}
+
+// One side of an offer, e.g. 50 Gold Coin
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TradeItem {
+    pub item: String,
+    pub amount: i32,
+}
+
+impl Display for TradeItem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.amount, self.item)
+    }
+}
+
+pub fn describe_items(items: &[TradeItem]) -> String {
+    if items.is_empty() {
+        return "nothing".to_string();
+    }
+    items.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+// Emitted when both sides accepted and the items were swapped
+#[derive(Event, Clone)]
+pub struct TradeCompletedEvent {
+    pub proposer: String,
+    pub responder: String,
+    pub proposer_gave: Vec<(Item, i32)>,
+    pub responder_gave: Vec<(Item, i32)>,
+}
+
+impl TradeCompletedEvent {
+    // Value of what the named side gave and got, zero for both when they weren't part of the trade
+    pub fn values_for(&self, name: &str) -> (i32, i32) {
+        if name == self.proposer {
+            (value(&self.proposer_gave), value(&self.responder_gave))
+        } else if name == self.responder {
+            (value(&self.responder_gave), value(&self.proposer_gave))
+        } else {
+            (0, 0)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TradeError {
+    UnknownItem(ItemLookupError),
+    Empty,
+    NoPendingTrade {
+        with: String,
+    },
+    OwnOffer,
+    // The npc would get less than its margin allows
+    BelowMargin {
+        npc: String,
+        offered: i32,
+        required: i32,
+    },
+    // Gave away too much with Give instead of trading it
+    GiftTooValuable {
+        value: i32,
+    },
+    Inventory(InventoryError),
+    // E.g. trading with a character that can't hold items
+    NoInventory(String),
+}
+
+impl Display for TradeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TradeError::UnknownItem(err) => write!(f, "{}", err),
+            TradeError::Empty => write!(f, "an offer has to give or want something"),
+            TradeError::NoPendingTrade { with } => write!(f, "there is no offer from {} to answer", with),
+            TradeError::OwnOffer => write!(f, "only the other side can accept an offer"),
+            TradeError::BelowMargin { npc, offered, required } => {
+                write!(f, "{} would get {} gold worth but wants at least {} gold worth", npc, offered, required)
+            }
+            TradeError::GiftTooValuable { value } => {
+                write!(f, "a gift worth {} gold is too much, gifts are worth at most {}, make an Offer instead", value, MAX_GIFT_VALUE)
+            }
+            TradeError::Inventory(err) => write!(f, "{}", err),
+            TradeError::NoInventory(name) => write!(f, "{} can't hold items", name),
+        }
+    }
+}
+
+impl std::error::Error for TradeError {}
+
+// An offer waiting for an answer. What the proposer gives is held in escrow, so they can't give it away in the meantime
+struct Trade {
+    proposer: Entity,
+    responder: Entity,
+    proposer_name: String,
+    responder_name: String,
+    give: Vec<(Item, i32)>,
+    want: Vec<(Item, i32)>,
+    escrow: Inventory,
+    // Time::elapsed_seconds when the offer was made
+    offered_at: f32,
+}
+
+impl Trade {
+    fn between(&self, a: Entity, b: Entity) -> bool {
+        (self.proposer == a && self.responder == b) || (self.proposer == b && self.responder == a)
+    }
+}
+
+#[derive(Resource)]
+pub struct Trades {
+    // At most one per pair of characters, a new offer replaces the old one
+    pending: Vec<Trade>,
+}
+
+impl Trades {
+    // What the character has offered and is still held in escrow. Saves count it as theirs, open offers aren't saved
+    pub fn escrowed(&self, proposer: Entity) -> impl Iterator<Item = (&Item, &i32)> {
+        self.pending.iter().filter(move |trade| trade.proposer == proposer).flat_map(|trade| trade.escrow.items())
+    }
+
+    // Calls off every open offer and returns the escrow, e.g. before a save is loaded over the current state
+    pub fn cancel_all(&mut self, inventory_query: &mut Query<&mut Inventory>) {
+        for trade in self.pending.drain(..) {
+            if let Ok(mut inventory) = inventory_query.get_mut(trade.proposer) {
+                return_escrow(&trade, &mut inventory);
+            }
+        }
+    }
+}
+
+fn resolve_items(item_registry: &ItemRegistry, items: &[TradeItem]) -> Result<Vec<(Item, i32)>, TradeError> {
+    items
+        .iter()
+        .map(|entry| {
+            let item = item_registry.resolve(&entry.item).map_err(TradeError::UnknownItem)?;
+            if entry.amount <= 0 || entry.amount > MAX_AMOUNT {
+                return Err(TradeError::Inventory(InventoryError::InvalidAmount(entry.amount)));
+            }
+            Ok((item.clone(), entry.amount))
+        })
+        .collect()
+}
+
+fn value(items: &[(Item, i32)]) -> i32 {
+    items.iter().fold(0, |total, (item, amount)| total.saturating_add(item.value(*amount)))
+}
+
+// Puts the items back in the proposer's inventory. Only fails when the proposer filled up in the meantime
+fn return_escrow(trade: &Trade, inventory: &mut Inventory) {
+    for (item, amount) in trade.escrow.items() {
+        if let Err(err) = inventory.add(item, *amount) {
+            warn!("Could not return {} {} from escrow to {}: {}", amount, item.name, trade.proposer_name, err);
+        }
+    }
+}
+
+// Everything the executor needs to run the Offer, Accept and Reject actions
+#[derive(SystemParam)]
+pub struct TradeDesk<'w, 's> {
+    time: Res<'w, Time>,
+    trades: ResMut<'w, Trades>,
+    npc_query: Query<'w, 's, (&'static Npc, &'static Relationships)>,
+    on_trade_completed: EventWriter<'w, TradeCompletedEvent>,
+}
+
+impl TradeDesk<'_, '_> {
+    // Npcs only agree to trades where they get at least what they give plus their margin.
+    // Customers they like get part of the margin back as a discount. Players can make any deal they want,
+    // and npcs trade at even value among themselves since both can't make a margin on the same deal
+    fn check_margin(&self, (entity, other): (Entity, Entity), other_name: &str, gets: i32, gives: i32) -> Result<(), TradeError> {
+        let Ok((npc, relationships)) = self.npc_query.get(entity) else {
+            return Ok(());
+        };
+        let percent = if self.npc_query.contains(other) {
+            100
+        } else {
+            margin_percent(npc.min_margin_percent, relationships.get(other_name).discount_percent())
+        };
+        let required = required_value(gives, percent);
+        if gets < required {
+            return Err(TradeError::BelowMargin { npc: npc.name.clone(), offered: gets, required });
+        }
+        Ok(())
+    }
+
+    // Cancels any earlier offer between the two and escrows what the proposer gives
+    pub fn offer(
+        &mut self,
+        inventory_query: &mut Query<&mut Inventory>,
+        item_registry: &ItemRegistry,
+        (proposer, proposer_name): (Entity, &str),
+        (responder, responder_name): (Entity, &str),
+        give: &[TradeItem],
+        want: &[TradeItem],
+    ) -> Result<(), TradeError> {
+        let give = resolve_items(item_registry, give)?;
+        let want = resolve_items(item_registry, want)?;
+        if give.is_empty() && want.is_empty() {
+            return Err(TradeError::Empty);
+        }
+        self.check_margin((proposer, responder), responder_name, value(&want), value(&give))?;
+        self.cancel(inventory_query, proposer, responder);
+
+        let Ok(mut inventory) = inventory_query.get_mut(proposer) else {
+            return Err(TradeError::NoInventory(proposer_name.to_string()));
+        };
+        let (mut after, mut escrow) = (inventory.clone(), Inventory::default());
+        for (item, amount) in &give {
+            Inventory::transfer(&mut after, &mut escrow, item, *amount).map_err(TradeError::Inventory)?;
+        }
+        *inventory = after;
+        self.trades.pending.push(Trade {
+            proposer,
+            responder,
+            proposer_name: proposer_name.to_string(),
+            responder_name: responder_name.to_string(),
+            give,
+            want,
+            escrow,
+            offered_at: self.time.elapsed_seconds(),
+        });
+        Ok(())
+    }
+
+    // Completes the offer the other side made to responder. Either every item moves or none do and the offer stays open
+    pub fn accept(
+        &mut self,
+        inventory_query: &mut Query<&mut Inventory>,
+        (responder, responder_name): (Entity, &str),
+        (proposer, proposer_name): (Entity, &str),
+    ) -> Result<(), TradeError> {
+        let Some(index) = self.trades.pending.iter().position(|trade| trade.proposer == proposer && trade.responder == responder) else {
+            if self.trades.pending.iter().any(|trade| trade.proposer == responder && trade.responder == proposer) {
+                return Err(TradeError::OwnOffer);
+            }
+            return Err(TradeError::NoPendingTrade { with: proposer_name.to_string() });
+        };
+        let trade = &self.trades.pending[index];
+        self.check_margin((responder, proposer), proposer_name, value(&trade.give), value(&trade.want))?;
+        self.check_margin((proposer, responder), responder_name, value(&trade.want), value(&trade.give))?;
+
+        let [mut proposer_inventory, mut responder_inventory] = inventory_query
+            .get_many_mut([proposer, responder])
+            .map_err(|_| TradeError::NoInventory(format!("{} or {}", proposer_name, responder_name)))?;
+        let (mut proposer_after, mut responder_after, mut escrow) = (proposer_inventory.clone(), responder_inventory.clone(), trade.escrow.clone());
+        for (item, amount) in &trade.want {
+            Inventory::transfer(&mut responder_after, &mut proposer_after, item, *amount).map_err(TradeError::Inventory)?;
+        }
+        for (item, amount) in &trade.give {
+            Inventory::transfer(&mut escrow, &mut responder_after, item, *amount).map_err(TradeError::Inventory)?;
+        }
+        *proposer_inventory = proposer_after;
+        *responder_inventory = responder_after;
+
+        let trade = self.trades.pending.remove(index);
+        info!("{} and {} traded", trade.proposer_name, trade.responder_name);
+        self.on_trade_completed.send(TradeCompletedEvent {
+            proposer: trade.proposer_name,
+            responder: trade.responder_name,
+            proposer_gave: trade.give,
+            responder_gave: trade.want,
+        });
+        Ok(())
+    }
+
+    // Either side can call off an open offer between the two, the escrow goes back to the proposer
+    pub fn reject(&mut self, inventory_query: &mut Query<&mut Inventory>, a: Entity, b: Entity, other_name: &str) -> Result<(), TradeError> {
+        if !self.cancel(inventory_query, a, b) {
+            return Err(TradeError::NoPendingTrade { with: other_name.to_string() });
+        }
+        Ok(())
+    }
+
+    fn cancel(&mut self, inventory_query: &mut Query<&mut Inventory>, a: Entity, b: Entity) -> bool {
+        let Some(index) = self.trades.pending.iter().position(|trade| trade.between(a, b)) else {
+            return false;
+        };
+        let trade = self.trades.pending.remove(index);
+        if let Ok(mut inventory) = inventory_query.get_mut(trade.proposer) {
+            return_escrow(&trade, &mut inventory);
+        }
+        true
+    }
+}
+
+// What an npc asks in percent of the value it gives. The discount only eats into the margin, npcs never sell below value
+fn margin_percent(min_margin_percent: i32, discount_percent: i32) -> i32 {
+    (100 + min_margin_percent - discount_percent).max(100)
+}
+
+// Rounded up, a margin is never given away to rounding. Done in i64 so large trades can't overflow
+fn required_value(gives: i32, percent: i32) -> i32 {
+    ((gives as i64 * percent as i64 + 99) / 100).min(i32::MAX as i64) as i32
+}
+
+// Calls off offers that went unanswered, and offers whose characters are gone
+fn expire_trades(time: Res<Time>, mut trades: ResMut<Trades>, mut inventory_query: Query<&mut Inventory>) {
+    let now = time.elapsed_seconds();
+    trades.pending.retain(|trade| {
+        let gone = inventory_query.get(trade.responder).is_err();
+        if !gone && now - trade.offered_at < TRADE_TIMEOUT_SECONDS {
+            return true;
+        }
+        info!("The offer of {} to {} expired", trade.proposer_name, trade.responder_name);
+        if let Ok(mut inventory) = inventory_query.get_mut(trade.proposer) {
+            return_escrow(trade, &mut inventory);
+        }
+        false
+    });
+}
+
+// Lets the player trade from the chat: "/offer 50 gold coin for 1 steel sword", "/accept" and "/reject".
+// None when the message isn't a trade command, an error with the usage when it's malformed
+pub fn parse_trade_command(message: &str) -> Option<Result<Action, String>> {
+    let message = message.trim();
+    let (command, rest) = message.split_once(' ').unwrap_or((message, ""));
+    match command.to_lowercase().as_str() {
+        "/accept" => Some(Ok(Action::Accept)),
+        "/reject" => Some(Ok(Action::Reject)),
+        "/offer" => Some(parse_offer(rest).ok_or_else(|| "Usage: /offer 50 gold coin for 1 steel sword".to_string())),
+        _ => None,
+    }
+}
+
+fn parse_offer(offer: &str) -> Option<Action> {
+    let (give, want) = offer.split_once(" for ")?;
+    Some(Action::Offer { give: parse_items(give)?, want: parse_items(want)? })
+}
+
+// "50 gold coin, 1 steel sword and 2 bread", or "nothing"
+fn parse_items(list: &str) -> Option<Vec<TradeItem>> {
+    let list = list.trim();
+    if list.eq_ignore_ascii_case("nothing") {
+        return Some(Vec::new());
+    }
+    list.split(',')
+        .flat_map(|part| part.split(" and "))
+        .map(|entry| {
+            let (amount, item) = entry.trim().split_once(' ')?;
+            let amount = amount.parse().ok().filter(|amount| (1..=MAX_AMOUNT).contains(amount))?;
+            Some(TradeItem { item: item.trim().to_string(), amount })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(command: &str) -> (Vec<TradeItem>, Vec<TradeItem>) {
+        match parse_trade_command(command) {
+            Some(Ok(Action::Offer { give, want })) => (give, want),
+            other => panic!("{} isn't an offer: {:?}", command, other),
+        }
+    }
+
+    fn items(list: &[TradeItem]) -> Vec<(&str, i32)> {
+        list.iter().map(|entry| (entry.item.as_str(), entry.amount)).collect()
+    }
+
+    #[test]
+    fn parses_offers() {
+        let (give, want) = offer("/offer 50 gold coin for 1 steel sword");
+        assert_eq!(items(&give), vec![("gold coin", 50)]);
+        assert_eq!(items(&want), vec![("steel sword", 1)]);
+
+        let (give, want) = offer("  /OFFER 2 bread, 1 apple and 3 fish for nothing  ");
+        assert_eq!(items(&give), vec![("bread", 2), ("apple", 1), ("fish", 3)]);
+        assert!(want.is_empty());
+    }
+
+    #[test]
+    fn parses_accept_and_reject() {
+        assert!(matches!(parse_trade_command("/accept"), Some(Ok(Action::Accept))));
+        assert!(matches!(parse_trade_command("/Reject please"), Some(Ok(Action::Reject))));
+    }
+
+    #[test]
+    fn leaves_other_messages_alone() {
+        assert!(parse_trade_command("I offer 50 gold coin for 1 steel sword").is_none());
+        assert!(parse_trade_command("").is_none());
+        assert!(parse_trade_command("/trade 1 apple").is_none());
+    }
+
+    #[test]
+    fn refuses_malformed_offers() {
+        for command in [
+            "/offer",
+            "/offer 50 gold coin",
+            "/offer 50 gold coin for",
+            "/offer gold coin for 1 steel sword",
+            "/offer 0 gold coin for 1 steel sword",
+            "/offer -5 gold coin for 1 steel sword",
+            "/offer 50 gold coin, for 1 steel sword",
+            "/offer 99999999999 gold coin for 1 steel sword",
+        ] {
+            assert!(matches!(parse_trade_command(command), Some(Err(_))), "{} was accepted", command);
+        }
+    }
+
+    #[test]
+    fn amounts_are_capped() {
+        let (give, _) = offer(&format!("/offer {} gold coin for nothing", MAX_AMOUNT));
+        assert_eq!(items(&give), vec![("gold coin", MAX_AMOUNT)]);
+        assert!(matches!(parse_trade_command(&format!("/offer {} gold coin for nothing", MAX_AMOUNT + 1)), Some(Err(_))));
+    }
+
+    #[test]
+    fn margin_is_rounded_up() {
+        assert_eq!(required_value(100, 110), 110);
+        assert_eq!(required_value(10, 115), 12);
+        assert_eq!(required_value(1, 101), 2);
+        assert_eq!(required_value(0, 110), 0);
+    }
+
+    #[test]
+    fn margin_never_overflows() {
+        assert_eq!(required_value(i32::MAX, 200), i32::MAX);
+    }
+
+    #[test]
+    fn discount_only_eats_into_the_margin() {
+        assert_eq!(margin_percent(10, 0), 110);
+        assert_eq!(margin_percent(10, 10), 100);
+        assert_eq!(margin_percent(10, 20), 100);
+        assert_eq!(margin_percent(0, 0), 100);
+        assert_eq!(margin_percent(-20, 0), 100);
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use bevy::log::warn;
+use sha2::{Digest, Sha256};
+use crate::tts::{SpeechRequest, TtsBackend, TtsFuture};
+
+// Keeps every generated line on disk, named after the hash of the backend, voice profile and text.
+// Lines that were spoken before are read back instead of generated again
+pub struct CachedBackend {
+    inner: Arc<dyn TtsBackend>,
+    dir: PathBuf,
+}
+
+impl CachedBackend {
+    pub fn new(inner: Arc<dyn TtsBackend>, dir: PathBuf) -> CachedBackend {
+        CachedBackend {
+            inner,
+            dir,
+        }
+    }
+
+    fn path(&self, request: &SpeechRequest) -> PathBuf {
+        let mut hasher = Sha256::new();
+        let voice = serde_json::to_string(&request.voice).unwrap_or_default();
+        // Separated by a zero byte so e.g. voice "a" + text "bc" doesn't collide with voice "ab" + text "c"
+        for part in [self.inner.id().as_str(), voice.as_str(), request.text.as_str()] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        self.dir.join(format!("{:x}.wav", hasher.finalize()))
+    }
+}
+
+impl TtsBackend for CachedBackend {
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    fn synthesize<'a>(&'a self, request: &'a SpeechRequest) -> TtsFuture<'a> {
+        Box::pin(async move {
+            let path = self.path(request);
+            if let Ok(audio) = tokio::fs::read(&path).await {
+                return Ok(audio);
+            }
+            let audio = self.inner.synthesize(request).await?;
+            if !audio.is_empty() {
+                // A cache that can't be written only costs time, the line can still be played
+                if let Err(err) = write_atomic(&path, &audio).await {
+                    warn!("Could not cache tts audio in {}: {}", path.display(), err);
+                }
+            }
+            Ok(audio)
+        })
+    }
+}
+
+// Written under a temporary name first so a half written file is never read back as a cached line
+async fn write_atomic(path: &PathBuf, audio: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let tmp = path.with_extension("wav.tmp");
+    tokio::fs::write(&tmp, audio).await?;
+    tokio::fs::rename(&tmp, path).await
+}
@@ -0,0 +1,40 @@
+use crate::tts::{SpeechRequest, TtsBackend, TtsFuture};
+
+pub const DEFAULT_API_URL: &str = "http://localhost:4003";
+
+// Talks to the tts server (POST /generate with a json body of the text and the voice profile)
+pub struct HttpTtsBackend {
+    http_client: reqwest::Client,
+    url: String,
+}
+
+impl HttpTtsBackend {
+    pub fn new(url: String) -> HttpTtsBackend {
+        HttpTtsBackend {
+            http_client: reqwest::Client::new(),
+            url,
+        }
+    }
+}
+
+impl TtsBackend for HttpTtsBackend {
+    fn id(&self) -> String {
+        format!("http:{}", self.url)
+    }
+
+    fn synthesize<'a>(&'a self, request: &'a SpeechRequest) -> TtsFuture<'a> {
+        Box::pin(async move {
+            let uri = format!("{}/generate", self.url);
+            let audio = self.http_client
+                .post(uri)
+                .json::<SpeechRequest>(request)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+
+            Ok(audio.to_vec())
+        })
+    }
+}
@@ -0,0 +1,120 @@
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use bevy::app::{App, Plugin};
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+use crate::tts::cache::CachedBackend;
+use crate::tts::http::HttpTtsBackend;
+use crate::tts::null::NullBackend;
+
+pub mod cache;
+pub mod http;
+pub mod null;
+
+const DEFAULT_CACHE_DIR: &str = "tts_cache";
+
+// Boxed future returned by the backends so they can be used as a trait object.
+// Resolves to the wav bytes, which are empty when there is nothing to play
+pub type TtsFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<u8>, TtsError>> + Send + 'a>>;
+
+#[derive(Debug)]
+pub enum TtsError {
+    Http(reqwest::Error),
+    Io(std::io::Error),
+}
+
+impl Display for TtsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TtsError::Http(err) => write!(f, "http error: {}", err),
+            TtsError::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TtsError {}
+
+impl From<reqwest::Error> for TtsError {
+    fn from(err: reqwest::Error) -> Self {
+        TtsError::Http(err)
+    }
+}
+
+impl From<std::io::Error> for TtsError {
+    fn from(err: std::io::Error) -> Self {
+        TtsError::Io(err)
+    }
+}
+
+// How a character sounds. Every field is a hint, backends ignore what they can't do
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct VoiceProfile {
+    // Speaker or style reference, e.g. a reference recording the server clones. The server's default voice when None
+    pub speaker: Option<String>,
+    // 1.0 is normal speed, 0.8 is slower
+    pub speed: f32,
+    // Shift in semitones, negative is deeper
+    pub pitch: f32,
+    // E.g. calm, happy, angry
+    pub emotion: Option<String>,
+}
+
+impl Default for VoiceProfile {
+    fn default() -> Self {
+        VoiceProfile {
+            speaker: None,
+            speed: 1.0,
+            pitch: 0.0,
+            emotion: None,
+        }
+    }
+}
+
+// A line to speak
+#[derive(Serialize, Clone, Debug)]
+pub struct SpeechRequest {
+    pub text: String,
+    pub voice: VoiceProfile,
+}
+
+// Anything that can turn text into wav audio
+pub trait TtsBackend: Send + Sync {
+    // Identifies the backend and its settings, part of the cache key so different backends don't share audio
+    fn id(&self) -> String;
+
+    fn synthesize<'a>(&'a self, request: &'a SpeechRequest) -> TtsFuture<'a>;
+}
+
+// The backend npc voices are generated with. Picked once at startup, see TtsPlugin
+#[derive(Resource, Clone)]
+pub struct Tts {
+    pub backend: Arc<dyn TtsBackend>,
+}
+
+// Selects the backend from the environment:
+// TTS_BACKEND    http (default) | none
+// TTS_API_URL    base url of the tts server, defaults to http://localhost:4003
+// TTS_CACHE_DIR  where generated audio is kept, defaults to tts_cache
+pub struct TtsPlugin;
+
+impl Plugin for TtsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Tts { backend: backend_from_env() });
+    }
+}
+
+fn backend_from_env() -> Arc<dyn TtsBackend> {
+    match env::var("TTS_BACKEND").unwrap_or_default().as_str() {
+        "none" => Arc::new(NullBackend),
+        _ => {
+            let url = env::var("TTS_API_URL").unwrap_or(http::DEFAULT_API_URL.to_string());
+            let cache_dir = env::var("TTS_CACHE_DIR").unwrap_or(DEFAULT_CACHE_DIR.to_string());
+            Arc::new(CachedBackend::new(Arc::new(HttpTtsBackend::new(url)), PathBuf::from(cache_dir)))
+        }
+    }
+}
@@ -2,7 +2,9 @@ from tts import generate_audio
 from http.server import BaseHTTPRequestHandler, HTTPServer
 import soundfile as sf
 import io
+import json
 import time
+from urllib.parse import unquote
 
 hostName = "localhost"
 serverPort = 4003
@@ -14,10 +16,20 @@ class Server(BaseHTTPRequestHandler):
             self.write_home_response()
         elif self.path.startswith("/generate"):
             text = self.path.split("/generate?text=")[1]
-            self.write_audio_response(text.replace("%20", " "))
-
-    def write_audio_response(self, text):
-        g_audio = generate_audio(text)  # NumPy array
+            self.write_audio_response(unquote(text))
+
+    # Body: {"text": "...", "voice": {"speaker": "path/to/reference.wav" or null, "speed": 1.0, "pitch": 0.0, "emotion": "calm" or null}}
+    def do_POST(self):
+        if self.path == "/generate":
+            length = int(self.headers.get("Content-Length", 0))
+            body = json.loads(self.rfile.read(length))
+            self.write_audio_response(body["text"], body.get("voice") or {})
+        else:
+            self.send_response(404)
+            self.end_headers()
+
+    def write_audio_response(self, text, voice=None):
+        g_audio = generate_audio(text, voice or {})  # NumPy array
 
         sample_rate = 24000  # Define sample rate or retrieve it from the audio generation
 
@@ -1,9 +1,22 @@
 from styletts2 import tts as style_tts
 import nltk
+import librosa
 import uuid
 
 tts = None
 
+sample_rate = 24000
+
+# Emotions map to how strongly the style is applied, higher is more expressive
+emotion_embedding_scale = {
+    "calm": 1.0,
+    "neutral": 1.0,
+    "sad": 1.2,
+    "happy": 1.5,
+    "angry": 2.0,
+    "excited": 2.0,
+}
+
 
 def init():
     global tts
@@ -15,7 +28,20 @@ def init():
     tts = style_tts.StyleTTS2()
 
 
-def generate_audio(text):
+# voice is the npc's voice profile, see VoiceProfile in the game. Missing fields use the defaults
+def generate_audio(text, voice):
     global tts
     file_name = uuid.uuid4()
-    return tts.inference(text, output_wav_file="tmp/" + str(file_name) + ".wav")
+    audio = tts.inference(
+        text,
+        target_voice_path=voice.get("speaker"),
+        embedding_scale=emotion_embedding_scale.get(voice.get("emotion"), 1.0),
+        output_wav_file="tmp/" + str(file_name) + ".wav",
+    )
+    speed = voice.get("speed", 1.0)
+    if speed > 0 and speed != 1.0:
+        audio = librosa.effects.time_stretch(audio, rate=speed)
+    pitch = voice.get("pitch", 0.0)
+    if pitch != 0.0:
+        audio = librosa.effects.pitch_shift(audio, sr=sample_rate, n_steps=pitch)
+    return audio